@@ -1,11 +1,13 @@
 /// LLM chaining example:
-/// 1. Use the first LLM to determine the sentiment of a given input.
-/// 2. Use the second LLM to summarize the sentiment.
+/// 1. Use the LLM to determine the sentiment of a given input.
+/// 2. Use the same LLM, with different settings, to summarize the sentiment.
 use asimov::prelude::*;
 use serde::Deserialize;
 
-#[derive(Deserialize, Debug)]
+/// Sentiment of a text.
+#[derive(Deserialize, AsimovOutput, Debug)]
 struct Sentiment {
+    /// true represents positive, false is negative.
     sentiment: bool,
 }
 
@@ -13,14 +15,7 @@ struct Sentiment {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
 
-    // First LLM for sentiment analysis
-    let sentiment_analyzer = OpenAiLlm::builder()
-        .temperature(0.0)
-        .model("gpt-3.5-turbo".to_string())
-        .build();
-
-    // Second LLM for summarizing sentiment
-    let summarizer = OpenAiLlm::builder()
+    let llm = OpenAiLlm::builder()
         .model("gpt-3.5-turbo".to_string())
         .temperature(0.0)
         .build();
@@ -29,15 +24,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let sentiment_prompt = prompt!(
         lines! {
-            "Return the sentiment, true represents positive, false is negative.",
-            "The output format should be like so: {\"sentiment\": true}",
-            "Q: {{input}}",
-            "A:"
+            "Return the sentiment of the following text.",
+            "Text: {{input}}"
         },
         input
     );
 
-    let sentiment: Sentiment = sentiment_analyzer.generate(&sentiment_prompt).await?;
+    // The format instructions derived from `Sentiment` are appended to the prompt.
+    let Structured(sentiment): Structured<Sentiment> = llm.generate(&sentiment_prompt).await?;
     let sent: bool = sentiment.sentiment;
 
     println!("Sentiment: {}", sent);
@@ -51,8 +45,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         sent
     );
 
-    // Use the second LLM to summarize the sentiment
-    let summary: RawString = summarizer.generate(&summary_prompt).await?;
+    // Summarize the sentiment, overriding the settings of the LLM for this call only
+    let options = GenerationOptions::builder()
+        .temperature(0.7)
+        .max_tokens(100)
+        .build();
+    let summary: RawString = llm.generate_with(&summary_prompt, &options).await?;
     println!("Summary: {}", summary.0);
 
     Ok(())
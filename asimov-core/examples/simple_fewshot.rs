@@ -1,6 +1,6 @@
 /// few shot generation example:
 /// 1. Adds few shot example to an in-memory vector db (HoraDb).
-/// 2. Retrieves 3 nearest few shot examples to a query.
+/// 2. Selects the 3 nearest few shot examples to a query with a `FewShotSelector`.
 /// 3. Use the retrieved examples to generate code for a given prompt using GPT-4.
 use asimov::prelude::*;
 use futures::StreamExt;
@@ -64,13 +64,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
     println!("Created query for generating a python function.");
 
-    let k = 3;
-    let few_shot_examples = db.knn(&namespace, &query, k).await?;
+    let selector = FewShotSelector::new(db, namespace).k(3).max_tokens(500);
+    let few_shot_examples = selector.render(&query).await?;
 
-    println!(
-        "Retrieved few-shot examples: {:?}",
-        few_shot_examples.render().unwrap()
-    );
+    println!("Retrieved few-shot examples: {:?}", few_shot_examples);
 
     let codegen_prompt = prompt!(
         lines! {
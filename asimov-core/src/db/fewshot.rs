@@ -0,0 +1,333 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use parking_lot::Mutex;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::{Embeddable, Input},
+    models::Embed,
+    tokenizers::{openai::OpenAiTiktoken, Tokenizer},
+};
+
+use super::space::VectorSpace;
+
+const TEMPLATE: &str = "{% for example in examples %}{{ example }}\n\n{% endfor %}";
+
+/// How [`FewShotSelector`] picks examples among the nearest ones.
+#[async_trait]
+pub trait Selection<I: Embeddable>: Send + Sync {
+    /// Number of candidates to retrieve to select `k` examples.
+    fn fetch_k(&self, k: usize) -> usize {
+        k
+    }
+
+    /// Pick `k` of `candidates`, ordered by similarity to `query`.
+    async fn select(&self, query: &str, candidates: Vec<I>, k: usize) -> Result<Vec<I>>;
+}
+
+/// Pick the examples nearest to the query.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Similarity;
+
+#[async_trait]
+impl<I: Embeddable> Selection<I> for Similarity {
+    async fn select(&self, _query: &str, mut candidates: Vec<I>, k: usize) -> Result<Vec<I>> {
+        candidates.truncate(k);
+        Ok(candidates)
+    }
+}
+
+/// Number of candidates embedded at the same time by [`Mmr`].
+const EMBED_CONCURRENCY: usize = 8;
+
+/// Maximal marginal relevance: pick examples near the query but far from
+/// each other, so that the prompt does not show the same example twice.
+///
+/// Candidates are embedded again with `embedder`, which should be the
+/// model of the vector space, e.g. shared with an `Arc`. Each selection
+/// embeds the query, plus the candidates not seen before: up to `fetch_k`
+/// requests, sent 8 at a time. Embeddings of the candidates are kept for
+/// the lifetime of the `Mmr`, and shared by its clones, so that a fixed
+/// set of examples is only embedded once.
+#[derive(Debug, Clone)]
+pub struct Mmr<E> {
+    embedder: E,
+    lambda: f32,
+    fetch_k: Option<usize>,
+    /// Embeddings of the candidates seen so far, by hash of their key.
+    embeddings: Arc<Mutex<HashMap<u64, Vec<f32>>>>,
+}
+
+impl<E: Embed> Mmr<E> {
+    /// `lambda` trades relevance (1.0) for diversity (0.0).
+    pub fn new(embedder: E, lambda: f32) -> Self {
+        Self {
+            embedder,
+            lambda,
+            fetch_k: None,
+            embeddings: Default::default(),
+        }
+    }
+
+    /// Number of candidates to pick from. Defaults to `4 * k`.
+    pub fn fetch_k(mut self, fetch_k: usize) -> Self {
+        self.fetch_k = Some(fetch_k);
+        self
+    }
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    dot / (norm(a) * norm(b)).max(f32::EPSILON)
+}
+
+#[async_trait]
+impl<I: Embeddable, E: Embed> Selection<I> for Mmr<E> {
+    fn fetch_k(&self, k: usize) -> usize {
+        self.fetch_k.unwrap_or(4 * k).max(k)
+    }
+
+    async fn select(&self, query: &str, candidates: Vec<I>, k: usize) -> Result<Vec<I>> {
+        if !(0.0..=1.0).contains(&self.lambda) {
+            return Err(AsimovError::FewShot(format!(
+                "MMR lambda must be between 0 and 1, got {}",
+                self.lambda
+            )));
+        }
+
+        let query = self.embedder.embed(&query).await?;
+        let keys: Vec<I::Key> = candidates.iter().map(Embeddable::key).collect();
+        let hashes = keys.iter().map(Input::hash).collect::<Result<Vec<_>>>()?;
+
+        // Indices of the candidates to embed, once per key.
+        let missing: HashMap<u64, usize> = {
+            let cached = self.embeddings.lock();
+            hashes
+                .iter()
+                .enumerate()
+                .filter(|(_, hash)| !cached.contains_key(hash))
+                .map(|(i, hash)| (*hash, i))
+                .collect()
+        };
+        let (embedder, keys) = (&self.embedder, &keys);
+        let embedded: Vec<(u64, Vec<f32>)> = futures::stream::iter(missing)
+            .map(|(hash, i)| async move {
+                Ok::<_, AsimovError>((hash, embedder.embed(&keys[i]).await?))
+            })
+            .buffer_unordered(EMBED_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let embeddings: Vec<Vec<f32>> = {
+            let mut cached = self.embeddings.lock();
+            cached.extend(embedded);
+            hashes.iter().map(|hash| cached[hash].clone()).collect()
+        };
+
+        let mut candidates: Vec<Option<I>> = candidates.into_iter().map(Some).collect();
+        let mut selected: Vec<usize> = vec![];
+        while selected.len() < k.min(candidates.len()) {
+            let score = |i: usize| {
+                let redundancy = selected
+                    .iter()
+                    .map(|&j| cosine(&embeddings[i], &embeddings[j]))
+                    .fold(0.0, f32::max);
+                self.lambda * cosine(&query, &embeddings[i]) - (1.0 - self.lambda) * redundancy
+            };
+            let best = (0..candidates.len())
+                .filter(|i| !selected.contains(i))
+                .max_by(|&a, &b| score(a).total_cmp(&score(b)))
+                .unwrap();
+            selected.push(best);
+        }
+
+        Ok(selected
+            .into_iter()
+            .filter_map(|i| candidates[i].take())
+            .collect())
+    }
+}
+
+/// Select few-shot examples from a [`VectorSpace`] namespace and render
+/// them into a prompt.
+///
+/// ```ignore
+/// let selector = FewShotSelector::new(db, "examples").k(3).max_tokens(500);
+/// let examples = selector.render(&query).await?;
+/// let prompt = prompt!("{{examples}}Prompt: {{query}}", examples, query);
+///
+/// // Or avoid picking near-duplicate examples.
+/// let selector = selector.select_with(Mmr::new(embedder, 0.5));
+/// ```
+pub struct FewShotSelector<V, S = Similarity, K = OpenAiTiktoken> {
+    pub space: V,
+    namespace: String,
+    k: usize,
+    max_tokens: Option<usize>,
+    template: String,
+    selection: S,
+    tokenizer: K,
+}
+
+impl<V> FewShotSelector<V> {
+    /// Select the nearest examples of `namespace`, counting tokens with
+    /// [`OpenAiTiktoken`].
+    pub fn new(space: V, namespace: impl Into<String>) -> Self {
+        Self::with_tokenizer(space, namespace, OpenAiTiktoken::new())
+    }
+}
+
+impl<V, K: Tokenizer> FewShotSelector<V, Similarity, K> {
+    pub fn with_tokenizer(space: V, namespace: impl Into<String>, tokenizer: K) -> Self {
+        Self {
+            space,
+            namespace: namespace.into(),
+            k: 3,
+            max_tokens: None,
+            template: TEMPLATE.to_string(),
+            selection: Similarity,
+            tokenizer,
+        }
+    }
+}
+
+impl<V, S, K> FewShotSelector<V, S, K> {
+    /// Number of examples to select. Defaults to 3.
+    pub fn k(mut self, k: usize) -> Self {
+        self.k = k;
+        self
+    }
+
+    /// Drop the examples that do not fit in `tokens`, least relevant first.
+    pub fn max_tokens(mut self, tokens: usize) -> Self {
+        self.max_tokens = Some(tokens);
+        self
+    }
+
+    /// Render with a Tera `template` instead, given the rendered
+    /// `examples` and the `query`.
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Pick the examples with `selection` instead.
+    pub fn select_with<S2>(self, selection: S2) -> FewShotSelector<V, S2, K> {
+        FewShotSelector {
+            space: self.space,
+            namespace: self.namespace,
+            k: self.k,
+            max_tokens: self.max_tokens,
+            template: self.template,
+            selection,
+            tokenizer: self.tokenizer,
+        }
+    }
+}
+
+impl<V, S, K> FewShotSelector<V, S, K>
+where
+    V: VectorSpace + Sync,
+    S: Selection<V::Item>,
+    K: Tokenizer,
+{
+    /// Examples for `query`, most relevant first.
+    pub async fn select<I: Input>(&self, query: &I) -> Result<Vec<V::Item>> {
+        let fetch_k = self.selection.fetch_k(self.k);
+        let candidates = self.space.knn(&self.namespace, query, fetch_k).await?;
+        let examples = self
+            .selection
+            .select(&query.render()?, candidates, self.k)
+            .await?;
+
+        let Some(max_tokens) = self.max_tokens else {
+            return Ok(examples);
+        };
+        let mut tokens = 0;
+        let mut fitting = vec![];
+        for example in examples {
+            let length = self.tokenizer.num_tokens(&example)?;
+            if tokens + length <= max_tokens {
+                tokens += length;
+                fitting.push(example);
+            }
+        }
+        Ok(fitting)
+    }
+
+    /// Examples for `query`, rendered with the template.
+    pub async fn render<I: Input>(&self, query: &I) -> Result<String> {
+        let examples = self
+            .select(query)
+            .await?
+            .iter()
+            .map(Input::render)
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut context = tera::Context::new();
+        context.insert("examples", &examples);
+        context.insert("query", &query.render()?);
+        tera::Tera::one_off(&self.template, &context, false)
+            .map_err(|e| AsimovError::FewShot(format!("Could not render examples: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::hora::HoraDb, models::mocks::Letters};
+
+    async fn examples() -> Result<HoraDb<Letters, String>> {
+        let mut db = HoraDb::new(Letters::default());
+        db.create_namespace("examples").await?;
+        let examples = ["aaab", "aaaa", "bbbb"].map(String::from);
+        db.add_items("examples", examples).await?;
+        Ok(db)
+    }
+
+    #[tokio::test]
+    async fn test_similarity() -> Result<()> {
+        let selector = FewShotSelector::new(examples().await?, "examples").k(2);
+        assert_eq!(selector.select(&"aaab").await?, ["aaab", "aaaa"]);
+
+        let selector = selector.template("{{ examples | join(sep=\", \") }} -> {{ query }}");
+        assert_eq!(selector.render(&"aaab").await?, "aaab, aaaa -> aaab");
+
+        let selector = selector.max_tokens(0);
+        assert!(selector.select(&"aaab").await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mmr() -> Result<()> {
+        let selector = FewShotSelector::new(examples().await?, "examples")
+            .k(2)
+            .select_with(Mmr::new(Letters::default(), 0.3));
+        assert_eq!(selector.select(&"aaab").await?, ["aaab", "bbbb"]);
+
+        let selector = selector.select_with(Mmr::new(Letters::default(), 2.0));
+        assert!(matches!(
+            selector.select(&"aaab").await,
+            Err(AsimovError::FewShot(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mmr_embeddings_cached() -> Result<()> {
+        let mmr = Mmr::new(Letters::default(), 0.3);
+        let candidates = || ["aaab", "aaaa", "bbbb"].map(String::from).to_vec();
+
+        let selected = mmr.select("aaab", candidates(), 2).await?;
+        assert_eq!(selected, ["aaab", "bbbb"]);
+        let selected = mmr.select("bbbb", candidates(), 1).await?;
+        assert_eq!(selected, ["bbbb"]);
+
+        // Both queries, and each candidate once.
+        assert_eq!(mmr.embedder.calls(), 5);
+        Ok(())
+    }
+}
@@ -11,11 +11,15 @@ use crate::{
     models::Embed,
 };
 
-use super::{namespace::Namespace, space::VectorSpace};
+use super::{
+    namespace::Namespace,
+    space::{check_dim, VectorSpace},
+};
 
 use typed_builder::TypedBuilder;
 
 struct HoraCollection {
+    dim: usize,
     collection: HNSWIndex<f32, u64>,
     store: HashMap<u64, Vec<f32>>,
 }
@@ -23,6 +27,7 @@ struct HoraCollection {
 impl HoraCollection {
     fn new(dim: usize) -> Self {
         Self {
+            dim,
             collection: HNSWIndex::new(
                 dim,
                 &hora::index::hnsw_params::HNSWParams::<f32>::default(),
@@ -110,6 +115,15 @@ where
             marker: std::marker::PhantomData,
         }
     }
+
+    /// Dimension of the vectors stored in the collection of `namespace`.
+    fn dim(&self, namespace: &Namespace) -> Result<usize> {
+        self.collections
+            .lock()
+            .get(namespace)
+            .map(|collection| collection.dim)
+            .ok_or_else(|| AsimovError::KeyNotFound(namespace.to_string()))
+    }
 }
 
 fn uuid_to_u64() -> u64 {
@@ -144,7 +158,7 @@ where
             return Err(AsimovError::KeyCollision(namespace.to_string()));
         }
 
-        let collection = HoraCollection::new(E::DIM as usize);
+        let collection = HoraCollection::new(self.llm.dim());
 
         self.collections.lock().insert(ns, collection);
 
@@ -170,15 +184,14 @@ where
         let ns = namespace
             .try_into()
             .map_err(|_| AsimovError::InvalidNamespace)?;
-        if !self.collections.lock().contains_key(&ns) {
-            return Err(AsimovError::KeyNotFound(namespace.to_string()));
-        }
+        let dim = self.dim(&ns)?;
 
         let mut points = Vec::new();
 
         for item in keys {
             let text = item.key().render()?;
             let embedding = self.llm.embed(&text).await?;
+            check_dim(dim, &embedding)?;
             let id = uuid_to_u64();
             self.store.insert(id, item);
             points.push((id, embedding));
@@ -190,9 +203,7 @@ where
             .get_mut(&ns)
             .ok_or(AsimovError::KeyNotFound(namespace.to_string()))?;
 
-        collection.add_batch(points).map_err(|_| {
-            AsimovError::Hora("Could not add vectors to the Hora collection.".to_string())
-        })?;
+        collection.add_batch(points)?;
 
         Ok(())
     }
@@ -222,13 +233,15 @@ where
     }
 
     async fn knn<K: Input>(&self, namespace: &str, query: &K, k: usize) -> Result<Vec<Self::Item>> {
-        let query_clone = query.render()?;
-
-        let embedding = self.llm.embed(&query_clone).await?;
-
         let ns = namespace
             .try_into()
             .map_err(|_| AsimovError::InvalidNamespace)?;
+        let dim = self.dim(&ns)?;
+
+        let query_clone = query.render()?;
+
+        let embedding = self.llm.embed(&query_clone).await?;
+        check_dim(dim, &embedding)?;
 
         let collections = self.collections.lock();
 
@@ -274,15 +287,34 @@ mod tests {
     #[async_trait]
     impl Embed for RandomMockEmbed {
         type Tokenizer = OpenAiTiktoken;
-        const DIM: u32 = 128;
+
+        fn dim(&self) -> usize {
+            128
+        }
 
         async fn embed<I: Input + ?Sized>(&self, _input: &I) -> Result<Vec<f32>> {
             let mut rng = StdRng::seed_from_u64(self.seed); // Use the seed to create a reproducible RNG
-            let embedding: Vec<f32> = (0..Self::DIM).map(|_| rng.gen_range(0.0..1.0)).collect();
+            let embedding: Vec<f32> = (0..self.dim()).map(|_| rng.gen_range(0.0..1.0)).collect();
             Ok(embedding)
         }
     }
 
+    /// Returns embeddings shorter than it claims.
+    pub struct ShortMockEmbed;
+
+    #[async_trait]
+    impl Embed for ShortMockEmbed {
+        type Tokenizer = OpenAiTiktoken;
+
+        fn dim(&self) -> usize {
+            128
+        }
+
+        async fn embed<I: Input + ?Sized>(&self, _input: &I) -> Result<Vec<f32>> {
+            Ok(vec![0.5; 64])
+        }
+    }
+
     #[tokio::test]
     async fn test_hora_db() -> Result<()> {
         let mut db = HoraDb::new(RandomMockEmbed::new(42));
@@ -290,7 +322,7 @@ mod tests {
         let namespace = "namespace1";
 
         // Test create_namespace
-        db.create_namespace(&namespace).await?;
+        db.create_namespace(namespace).await?;
 
         assert!(db
             .collections
@@ -305,16 +337,16 @@ mod tests {
             "test key 5".to_string(),
         ];
 
-        db.add_items(&namespace, keys).await?;
+        db.add_items(namespace, keys).await?;
 
         // Test knn
         let query = "test query".to_string();
         let k = 3;
-        let result = db.knn(&namespace, &query, k).await?;
+        let result = db.knn(namespace, &query, k).await?;
 
         assert_eq!(result.len(), k);
         // Test delete_namespace
-        db.delete_namespace(&namespace).await?;
+        db.delete_namespace(namespace).await?;
         assert!(!db
             .collections
             .lock()
@@ -322,4 +354,49 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_dimension_mismatch() -> Result<()> {
+        let mut db = HoraDb::new(ShortMockEmbed);
+        db.create_namespace("namespace1").await?;
+
+        let added = db.add_item("namespace1", "test key".to_string()).await;
+        assert!(matches!(added, Err(AsimovError::VectorDb(_))));
+
+        let found = db.knn("namespace1", &"test query", 1).await;
+        assert!(matches!(found, Err(AsimovError::VectorDb(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_namespace_dimension() -> Result<()> {
+        let mut db = HoraDb::new(RandomMockEmbed::new(42));
+        db.collections
+            .lock()
+            .insert("namespace1".try_into().unwrap(), HoraCollection::new(64));
+
+        let added = db.add_item("namespace1", "test key".to_string()).await;
+        assert!(matches!(added, Err(AsimovError::VectorDb(_))));
+
+        let found = db.knn("namespace1", &"test query", 1).await;
+        assert!(matches!(found, Err(AsimovError::VectorDb(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_knn_reranked() -> Result<()> {
+        let mut db = HoraDb::new(RandomMockEmbed::new(42));
+        db.create_namespace("namespace1").await?;
+        let keys = vec!["a".to_string(), "bbb".to_string(), "cc".to_string()];
+        db.add_items("namespace1", keys).await?;
+
+        let by_length = |_: &str, documents: &[String]| -> Result<Vec<f32>> {
+            Ok(documents.iter().map(|d| d.len() as f32).collect())
+        };
+        let result = db
+            .knn_reranked("namespace1", &"query", 2, 3, &by_length)
+            .await?;
+        assert_eq!(result, ["bbb", "cc"]);
+        Ok(())
+    }
 }
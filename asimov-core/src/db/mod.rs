@@ -2,6 +2,7 @@
 //!
 //! Module to interact with with vector databases.
 
+pub mod fewshot;
 pub mod hora;
 pub mod namespace;
 pub mod space;
@@ -19,7 +19,7 @@ use crate::{
     models::Embed,
 };
 
-use super::space::VectorSpace;
+use super::space::{check_dim, VectorSpace};
 
 pub struct Qdrant<E: Embed, I: Embeddable> {
     client: QdrantClient,
@@ -40,6 +40,24 @@ where
             marker: PhantomData,
         }
     }
+
+    /// Size of the vectors stored in the collection of `namespace`.
+    async fn dim(&self, namespace: &str) -> Result<usize> {
+        let info = self.client.collection_info(namespace).await?;
+        let size = info
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config)
+            .and_then(|config| match config {
+                Config::Params(params) => Some(params.size),
+                Config::ParamsMap(_) => None,
+            });
+        size.map(|size| size as usize).ok_or_else(|| {
+            AsimovError::VectorDb(format!("Could not find the vector size of {namespace}"))
+        })
+    }
 }
 
 #[async_trait]
@@ -59,7 +77,7 @@ where
                 collection_name: namespace.to_string(),
                 vectors_config: Some(VectorsConfig {
                     config: Some(Config::Params(VectorParams {
-                        size: E::DIM as u64,
+                        size: self.llm.dim() as u64,
                         distance: Distance::Cosine as i32,
                         ..Default::default()
                     })),
@@ -80,11 +98,13 @@ where
         It: IntoIterator<Item = Self::Item> + Send,
         <It as IntoIterator>::IntoIter: Send,
     {
+        let dim = self.dim(namespace).await?;
         let mut points = Vec::new();
 
         for item in items {
             let text = item.render()?;
             let embedding = self.llm.embed(&text).await?;
+            check_dim(dim, &embedding)?;
             let id: u64 = text.hash()?;
 
             let payload = json!({
@@ -119,8 +139,10 @@ where
     }
 
     async fn knn<K: Input>(&self, namespace: &str, query: &K, k: usize) -> Result<Vec<Self::Item>> {
+        let dim = self.dim(namespace).await?;
         let query_clone = query.clone();
         let query = self.llm.embed(&query_clone).await?;
+        check_dim(dim, &query)?;
         let response = self
             .client
             .search_points(&SearchPoints {
@@ -161,10 +183,13 @@ mod test {
     #[async_trait]
     impl Embed for MockEmbed {
         type Tokenizer = OpenAiTiktoken;
-        const DIM: u32 = 128;
+
+        fn dim(&self) -> usize {
+            128
+        }
 
         async fn embed<I: Input + ?Sized>(&self, _input: &I) -> Result<Vec<f32>> {
-            let embedding = vec![0.0; Self::DIM as usize];
+            let embedding = vec![0.0; self.dim()];
             // Mock embedding logic
             Ok(embedding)
         }
@@ -250,10 +275,10 @@ mod test {
     impl Embeddable for Inputs {
         type Key = String;
 
-        fn key(&self) -> &Self::Key {
+        fn key(&self) -> Self::Key {
             match self {
-                Inputs::Ex(e) => &e.text,
-                Inputs::Str(s) => s,
+                Inputs::Ex(e) => e.text.clone(),
+                Inputs::Str(s) => s.clone(),
             }
         }
     }
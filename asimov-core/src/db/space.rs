@@ -1,6 +1,11 @@
 use async_trait::async_trait;
 
-use crate::{error::Result, io::Embeddable, Input};
+use crate::{
+    error::{AsimovError, Result},
+    io::Embeddable,
+    models::Rerank,
+    Input,
+};
 
 #[async_trait]
 pub trait VectorSpace
@@ -25,4 +30,36 @@ where
     async fn delete_item(&mut self, namespace: &str, item: Self::Item) -> Result<()>;
 
     async fn knn<I: Input>(&self, namespace: &str, query: &I, k: usize) -> Result<Vec<Self::Item>>;
+
+    /// Fetch the `fetch_k` nearest items to `query`, then keep the `k` most
+    /// relevant according to `reranker`. Fetching more items than needed
+    /// lets the reranker recover relevant items that embeddings ranked low.
+    async fn knn_reranked<I, R>(
+        &self,
+        namespace: &str,
+        query: &I,
+        k: usize,
+        fetch_k: usize,
+        reranker: &R,
+    ) -> Result<Vec<Self::Item>>
+    where
+        Self: Sync,
+        I: Input,
+        R: Rerank,
+    {
+        let candidates = self.knn(namespace, query, fetch_k.max(k)).await?;
+        reranker.rerank(query, candidates, k).await
+    }
+}
+
+/// Check that `embedding` has the length of the vectors of the space, so
+/// that a misconfigured model fails loudly instead of corrupting the index.
+pub(crate) fn check_dim(expected: usize, embedding: &[f32]) -> Result<()> {
+    if embedding.len() != expected {
+        return Err(AsimovError::VectorDb(format!(
+            "Expected an embedding of dimension {expected}, got {}",
+            embedding.len()
+        )));
+    }
+    Ok(())
 }
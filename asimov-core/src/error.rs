@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,15 +10,34 @@ pub enum AsimovError {
     Output(String),
     #[error("Tokenizer error: {0}")]
     Tokenizer(String),
+    #[error("Splitter error: {0}")]
+    Splitter(String),
     #[error("Namespace error: {0}")]
     Namespace(String),
     #[cfg(feature = "openai")]
     #[error("OpenAI error")]
     OpenAI(#[from] async_openai::error::OpenAIError),
+    /// Error response to a request sent without async-openai, with its HTTP
+    /// status and the delay its headers asked for.
+    #[cfg(feature = "openai")]
+    #[error("OpenAI error: HTTP {status}: {}", .error.message)]
+    OpenAIStatus {
+        status: u16,
+        error: Box<async_openai::error::ApiError>,
+        retry_after: Option<Duration>,
+    },
     #[error("Parsing Error")]
     ParsingError(#[from] serde_json::error::Error),
     #[error("Model error: {0}")]
     Model(String),
+    #[error("Timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("Generation cancelled")]
+    Cancelled,
+    #[error("Cache error: {0}")]
+    Cache(String),
+    #[error("All models failed: {}", fallback_errors(.0))]
+    Fallback(Vec<AsimovError>),
     #[error("Key {0} is already present")]
     KeyCollision(String),
     #[error("Key {0} not found")]
@@ -38,3 +59,145 @@ pub enum AsimovError {
 }
 
 pub type Result<T, E = AsimovError> = std::result::Result<T, E>;
+
+/// List the errors of each model, e.g. "[0] Timed out after 10s, [1] ...".
+fn fallback_errors(errors: &[AsimovError]) -> String {
+    errors
+        .iter()
+        .enumerate()
+        .map(|(i, e)| format!("[{i}] {e}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl AsimovError {
+    /// Whether the request may succeed if sent again: rate limits, server
+    /// errors, timeouts and connection failures.
+    ///
+    /// async-openai does not report the HTTP status of failed requests, only
+    /// their body: errors are told apart by their type and code. A body that
+    /// is not JSON at all, e.g. the error page of a proxy or a load balancer,
+    /// is deemed a server failure. Chat completions, sent without
+    /// async-openai, keep their status and are told apart by it.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AsimovError::Timeout(_) => true,
+            #[cfg(feature = "openai")]
+            AsimovError::OpenAI(e) => openai::is_transient(e),
+            #[cfg(feature = "openai")]
+            AsimovError::OpenAIStatus { status, error, .. } => {
+                openai::is_transient_status(*status, error)
+            }
+            _ => false,
+        }
+    }
+
+    /// Delay requested by the provider before sending the request again.
+    ///
+    /// Chat completions, sent without async-openai, read the
+    /// `retry-after-ms` and `Retry-After` headers. async-openai 0.19 drops
+    /// the headers of the other requests, e.g. embeddings: there, and when
+    /// the headers are missing, the delay is parsed from the error message,
+    /// e.g. "Please try again in 1.5s".
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            #[cfg(feature = "openai")]
+            AsimovError::OpenAIStatus {
+                retry_after: Some(delay),
+                ..
+            } => Some(*delay),
+            #[cfg(feature = "openai")]
+            AsimovError::OpenAI(async_openai::error::OpenAIError::ApiError(e)) => {
+                parse_retry_after(&e.message)
+            }
+            #[cfg(feature = "openai")]
+            AsimovError::OpenAIStatus { error, .. } => parse_retry_after(&error.message),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "openai")]
+/// Extract the delay from messages such as "Please try again in 1.5s".
+fn parse_retry_after(message: &str) -> Option<Duration> {
+    let (_, rest) = message
+        .split_once("try again in ")
+        .or_else(|| message.split_once("retry after "))?;
+
+    let number: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    let value: f64 = number.parse().ok()?;
+
+    let unit = rest[number.len()..].trim_start();
+    let seconds = if unit.starts_with("ms") {
+        value / 1000.0
+    } else if unit.starts_with('s') {
+        value
+    } else if unit.starts_with('m') {
+        value * 60.0
+    } else {
+        return None;
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[cfg(feature = "openai")]
+mod openai {
+    use async_openai::error::{ApiError, OpenAIError};
+
+    /// Error types and codes of rate limits and server-side failures.
+    const TRANSIENT: [&str; 5] = [
+        "rate_limit_exceeded",
+        "requests",
+        "tokens",
+        "server_error",
+        "engine_overloaded",
+    ];
+
+    pub(super) fn is_transient(error: &OpenAIError) -> bool {
+        match error {
+            OpenAIError::Reqwest(e) => e.is_timeout() || e.is_connect(),
+            OpenAIError::ApiError(e) => {
+                let code = code(e);
+                let kind = e.r#type.as_deref();
+                code != Some("insufficient_quota")
+                    && [code, kind]
+                        .into_iter()
+                        .flatten()
+                        .any(|k| TRANSIENT.contains(&k))
+            }
+            OpenAIError::JSONDeserialize(e) => e.is_syntax() || e.is_eof(),
+            OpenAIError::StreamError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Timeouts, conflicts, rate limits other than an exhausted quota, and
+    /// server errors.
+    pub(super) fn is_transient_status(status: u16, error: &ApiError) -> bool {
+        code(error) != Some("insufficient_quota") && matches!(status, 408 | 409 | 429 | 500..)
+    }
+
+    fn code(error: &ApiError) -> Option<&str> {
+        error.code.as_ref().and_then(|c| c.as_str())
+    }
+}
+
+#[cfg(all(test, feature = "openai"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        let parse = |m| parse_retry_after(m).map(|d| d.as_millis());
+        assert_eq!(
+            parse("Rate limit reached for requests. Please try again in 1.5s. Visit ..."),
+            Some(1500)
+        );
+        assert_eq!(parse("Please try again in 20ms."), Some(20));
+        assert_eq!(parse("Please retry after 2 seconds."), Some(2000));
+        assert_eq!(parse("You exceeded your current quota."), None);
+    }
+}
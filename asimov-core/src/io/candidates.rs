@@ -0,0 +1,64 @@
+use derive_more::{Deref, DerefMut};
+use serde::Serialize;
+
+/// Several answers generated for the same input, in the order returned by
+/// the provider.
+///
+/// Generate `Candidates<T>` instead of `T` to get `n` answers from a single
+/// request (see [`GenerationOptions::n`](crate::models::GenerationOptions::n)),
+/// each parsed as `T` would be. It dereferences to the answers.
+#[derive(Debug, Clone, PartialEq, Deref, DerefMut, Serialize)]
+pub struct Candidates<T>(pub Vec<T>);
+
+impl<T> Candidates<T> {
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+
+    /// The most frequent answer, the earliest one on ties.
+    pub fn majority(self) -> Option<T>
+    where
+        T: PartialEq,
+    {
+        let mut votes: Vec<(T, usize)> = vec![];
+        for candidate in self.0 {
+            match votes.iter_mut().find(|(answer, _)| *answer == candidate) {
+                Some((_, count)) => *count += 1,
+                None => votes.push((candidate, 1)),
+            }
+        }
+
+        let max = votes.iter().map(|(_, count)| *count).max()?;
+        votes
+            .into_iter()
+            .find(|(_, count)| *count == max)
+            .map(|(answer, _)| answer)
+    }
+
+    /// The answer with the highest `score`, the earliest one on ties.
+    pub fn best_by(self, mut score: impl FnMut(&T) -> f64) -> Option<T> {
+        let mut best: Option<(T, f64)> = None;
+        for candidate in self.0 {
+            let score = score(&candidate);
+            if best.as_ref().is_none_or(|(_, best)| score > *best) {
+                best = Some((candidate, score));
+            }
+        }
+        best.map(|(answer, _)| answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_majority() {
+        let candidates = Candidates(vec!["b", "a", "c", "a", "b"]);
+        assert_eq!(candidates.clone().majority(), Some("b"));
+        assert_eq!(Candidates(vec!["c", "a", "a"]).majority(), Some("a"));
+        assert_eq!(Candidates::<&str>(vec![]).majority(), None);
+
+        assert_eq!(candidates.best_by(|c| c.len() as f64), Some("b"));
+    }
+}
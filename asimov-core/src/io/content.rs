@@ -0,0 +1,208 @@
+use std::{
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use twox_hash::XxHash64;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::Input,
+};
+
+/// Resolution at which a vision model looks at an image. `Low` costs fewer
+/// tokens, `High` lets the model see the details.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ImageDetail {
+    #[default]
+    Auto,
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ImageSource {
+    Url(String),
+    Bytes { data: Vec<u8>, mime_type: String },
+}
+
+/// An image in a prompt, for vision models.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Image {
+    source: ImageSource,
+    pub detail: ImageDetail,
+}
+
+impl Image {
+    /// Image at `url`, fetched by the provider.
+    pub fn from_url(url: impl Into<String>) -> Self {
+        Self {
+            source: ImageSource::Url(url.into()),
+            detail: ImageDetail::default(),
+        }
+    }
+
+    /// Image encoded as `mime_type`, e.g. `image/png`.
+    pub fn from_bytes(data: impl Into<Vec<u8>>, mime_type: impl Into<String>) -> Self {
+        Self {
+            source: ImageSource::Bytes {
+                data: data.into(),
+                mime_type: mime_type.into(),
+            },
+            detail: ImageDetail::default(),
+        }
+    }
+
+    /// Read the image at `path`. The format is guessed from the extension
+    /// of the file: PNG, JPEG, GIF or WebP.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        let mime_type = match extension.as_deref() {
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            _ => {
+                return Err(AsimovError::Input(format!(
+                    "Unsupported image format: {}",
+                    path.display()
+                )))
+            }
+        };
+
+        let data = std::fs::read(path)
+            .map_err(|e| AsimovError::Input(format!("{}: {e}", path.display())))?;
+        Ok(Self::from_bytes(data, mime_type))
+    }
+
+    pub fn with_detail(mut self, detail: ImageDetail) -> Self {
+        self.detail = detail;
+        self
+    }
+
+    /// URL of the image, as a base64 `data:` URL for images read from bytes
+    /// or files.
+    pub fn url(&self) -> String {
+        match &self.source {
+            ImageSource::Url(url) => url.clone(),
+            ImageSource::Bytes { data, mime_type } => {
+                format!("data:{mime_type};base64,{}", STANDARD.encode(data))
+            }
+        }
+    }
+}
+
+/// Part of the content of an input, see [`Input::content`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContentPart {
+    Text(String),
+    Image(Image),
+}
+
+impl ContentPart {
+    pub fn is_image(&self) -> bool {
+        matches!(self, ContentPart::Image(_))
+    }
+}
+
+/// Text of `parts`, without the images.
+pub(crate) fn text_content(parts: &[ContentPart]) -> String {
+    let texts: Vec<&str> = parts
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Text(text) => Some(text.as_str()),
+            ContentPart::Image(_) => None,
+        })
+        .collect();
+    texts.join("\n")
+}
+
+/// Prompt mixing text and images, for vision models.
+///
+/// ```ignore
+/// let input = Multimodal::new()
+///     .text("What is the trend in this chart?")
+///     .image(Image::from_path("chart.png")?.with_detail(ImageDetail::High));
+/// let answer: RawString = gpt4_vision.generate(input).await?;
+/// ```
+///
+/// Text-only models can't take images: rendering a prompt with images
+/// fails with [`AsimovError::Input`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Multimodal {
+    parts: Vec<ContentPart>,
+}
+
+impl Multimodal {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.parts.push(ContentPart::Text(text.into()));
+        self
+    }
+
+    pub fn image(mut self, image: Image) -> Self {
+        self.parts.push(ContentPart::Image(image));
+        self
+    }
+
+    pub fn parts(&self) -> &[ContentPart] {
+        &self.parts
+    }
+}
+
+impl Input for Multimodal {
+    fn render(&self) -> Result<String> {
+        if self.parts.iter().any(ContentPart::is_image) {
+            return Err(AsimovError::Input(
+                "The input contains images, which only vision models can take".to_string(),
+            ));
+        }
+        Ok(text_content(&self.parts))
+    }
+
+    fn content(&self) -> Result<Vec<ContentPart>> {
+        Ok(self.parts.clone())
+    }
+
+    fn hash(&self) -> Result<u64> {
+        let mut h = XxHash64::default();
+        Hash::hash(&self.parts, &mut h);
+        Ok(h.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multimodal() -> Result<()> {
+        let png = Image::from_bytes(*b"\x89PNG", "image/png").with_detail(ImageDetail::Low);
+        assert_eq!(png.url(), "data:image/png;base64,iVBORw==");
+
+        let input = Multimodal::new()
+            .text("What is in this image?")
+            .image(png)
+            .image(Image::from_url("https://example.com/cat.jpg"));
+        assert_eq!(input.content()?.len(), 3);
+        assert!(matches!(input.render(), Err(AsimovError::Input(_))));
+        assert_ne!(
+            input.hash()?,
+            Multimodal::new().text("What is in this image?").hash()?
+        );
+
+        let text = Multimodal::new().text("Hello").text("world");
+        assert_eq!(text.render()?, "Hello\nworld");
+
+        assert!(Image::from_path("chart.svg").is_err());
+        Ok(())
+    }
+}
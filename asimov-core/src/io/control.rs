@@ -0,0 +1,190 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures::{task::AtomicWaker, Stream, StreamExt};
+use tokio::time::{Instant, Sleep};
+
+use crate::{error::AsimovError, io::TokenStream};
+
+#[derive(Default, Debug)]
+struct CancelState {
+    cancelled: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// Handle to cancel a streamed generation from anywhere, e.g. another task.
+///
+/// Once cancelled, the stream yields a single [`AsimovError::Cancelled`] on
+/// its next poll and drops the underlying stream. Streams of `OpenAiLlm` own
+/// their HTTP response, so this closes the connection to the API right away.
+/// Streams built on top of a [`TokenStream`] share its handle.
+#[derive(Clone, Default, Debug)]
+pub struct CancelHandle(Arc<CancelState>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.waker.wake();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wake up the task polling the stream when cancelled.
+    pub(crate) fn register(&self, waker: &Waker) {
+        self.0.waker.register(waker);
+    }
+}
+
+/// Ends a token stream with [`AsimovError::Timeout`] when `sleep` elapses
+/// before the next token. With `idle`, the timer restarts on every token.
+struct Timeout {
+    tokens: Option<TokenStream>,
+    sleep: Pin<Box<Sleep>>,
+    idle: Option<Duration>,
+    /// Duration reported in the error.
+    duration: Duration,
+}
+
+impl Stream for Timeout {
+    type Item = Result<String, AsimovError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(tokens) = self.tokens.as_mut() else {
+            return Poll::Ready(None);
+        };
+
+        match tokens.poll_next_unpin(cx) {
+            Poll::Ready(Some(token)) => {
+                if let Some(idle) = self.idle {
+                    self.sleep.as_mut().reset(Instant::now() + idle);
+                }
+                Poll::Ready(Some(token))
+            }
+            Poll::Ready(None) => {
+                self.tokens = None;
+                Poll::Ready(None)
+            }
+            Poll::Pending => {
+                if self.sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.tokens = None;
+                Poll::Ready(Some(Err(AsimovError::Timeout(self.duration))))
+            }
+        }
+    }
+}
+
+impl TokenStream {
+    /// End the stream with [`AsimovError::Timeout`] if it is not exhausted
+    /// within `duration`.
+    pub fn timeout(self, duration: Duration) -> TokenStream {
+        self.timeout_at(Instant::now() + duration, duration)
+    }
+
+    /// End the stream with [`AsimovError::Timeout`] at `deadline`, reporting `duration`.
+    pub(crate) fn timeout_at(self, deadline: Instant, duration: Duration) -> TokenStream {
+        self.with_timer(deadline, None, duration)
+    }
+
+    /// End the stream with [`AsimovError::Timeout`] if no token arrives
+    /// within `duration` of the previous one, or of the call.
+    pub fn idle_timeout(self, duration: Duration) -> TokenStream {
+        self.with_timer(Instant::now() + duration, Some(duration), duration)
+    }
+
+    fn with_timer(self, deadline: Instant, idle: Option<Duration>, duration: Duration) -> Self {
+        let metadata = self.metadata_slot();
+        let cancel = self.cancel_handle();
+
+        let stream = Timeout {
+            tokens: Some(self),
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+            idle,
+            duration,
+        };
+
+        TokenStream::with_metadata(stream, metadata).with_cancel_handle(cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+    use async_stream::stream;
+
+    /// Tokens arriving after the given delays.
+    fn delayed(delays: &[u64]) -> TokenStream {
+        let delays = delays.to_vec();
+        TokenStream::new(stream! {
+            for (i, delay) in delays.into_iter().enumerate() {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                yield Ok(i.to_string());
+            }
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout() {
+        let tokens: Vec<Result<String>> = delayed(&[10, 10, 10])
+            .timeout(Duration::from_millis(25))
+            .collect()
+            .await;
+
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[1].as_ref().unwrap(), "1");
+        assert!(matches!(tokens[2], Err(AsimovError::Timeout(d)) if d.as_millis() == 25));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_timeout() {
+        let tokens: Vec<Result<String>> = delayed(&[10, 10, 10, 10])
+            .idle_timeout(Duration::from_millis(15))
+            .collect()
+            .await;
+        assert_eq!(tokens.len(), 4);
+        assert!(tokens.iter().all(Result::is_ok));
+
+        let tokens: Vec<Result<String>> = delayed(&[10, 20, 10])
+            .idle_timeout(Duration::from_millis(15))
+            .collect()
+            .await;
+        assert_eq!(tokens.len(), 2);
+        assert!(matches!(tokens[1], Err(AsimovError::Timeout(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel() {
+        let mut tokens = delayed(&[10, 1000, 10]).idle_timeout(Duration::from_secs(10));
+        let handle = tokens.cancel_handle();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            handle.cancel();
+        });
+
+        let start = Instant::now();
+        assert_eq!(tokens.next().await.unwrap().unwrap(), "0");
+        assert!(matches!(
+            tokens.next().await,
+            Some(Err(AsimovError::Cancelled))
+        ));
+        assert!(tokens.next().await.is_none());
+        assert!(start.elapsed() < Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{sync::Arc, time::Duration};
+
+use derive_more::{Deref, DerefMut};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Why the LLM stopped generating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// Natural end of the answer, or a stop sequence was reached.
+    Stop,
+    /// The maximum number of tokens was reached: the answer is truncated.
+    Length,
+    /// Content was omitted by the provider's content filter.
+    ContentFilter,
+    ToolCalls,
+    FunctionCall,
+}
+
+/// Number of tokens consumed by a generation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+/// Information about a generation, as reported by the provider.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationMetadata {
+    /// Model that actually served the request.
+    pub model: String,
+    /// Provider-side id of the request.
+    pub id: Option<String>,
+    pub finish_reason: Option<FinishReason>,
+    /// Token counts, when reported by the provider.
+    pub usage: Option<Usage>,
+    /// Time from sending the request to receiving the full answer.
+    pub latency: Duration,
+}
+
+impl GenerationMetadata {
+    /// Whether the generation stopped because it ran out of tokens.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason == Some(FinishReason::Length)
+    }
+}
+
+/// A generated value along with the metadata of its generation.
+///
+/// Generate `Generation<T>` instead of `T` to get the finish reason, token
+/// usage and latency of the request. It dereferences to the value.
+#[derive(Debug, Clone, PartialEq, Deref, DerefMut, Serialize)]
+pub struct Generation<T> {
+    #[deref]
+    #[deref_mut]
+    pub value: T,
+    pub metadata: GenerationMetadata,
+}
+
+impl<T> Generation<T> {
+    pub fn new(value: T, metadata: GenerationMetadata) -> Self {
+        Self { value, metadata }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// Transform the value, keeping the metadata.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Generation<U> {
+        Generation {
+            value: f(self.value),
+            metadata: self.metadata,
+        }
+    }
+
+    /// Transform the value with a fallible function, keeping the metadata.
+    pub fn try_map<U, E>(self, f: impl FnOnce(T) -> Result<U, E>) -> Result<Generation<U>, E> {
+        Ok(Generation {
+            value: f(self.value)?,
+            metadata: self.metadata,
+        })
+    }
+}
+
+/// Shared slot holding the metadata of a streamed generation.
+///
+/// It is empty until the stream has been consumed to the end.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataSlot(Arc<Mutex<Option<GenerationMetadata>>>);
+
+impl MetadataSlot {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn set(&self, metadata: GenerationMetadata) {
+        *self.0.lock() = Some(metadata);
+    }
+
+    pub fn get(&self) -> Option<GenerationMetadata> {
+        self.0.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation() {
+        let metadata = GenerationMetadata {
+            model: "gpt-3.5-turbo".to_string(),
+            finish_reason: Some(FinishReason::Length),
+            ..Default::default()
+        };
+        let generation = Generation::new("42".to_string(), metadata);
+
+        assert_eq!(generation.len(), 2);
+        assert!(generation.metadata.is_truncated());
+
+        let parsed = generation.try_map(|v| v.parse::<u8>()).unwrap();
+        assert_eq!(parsed.value, 42);
+        assert_eq!(parsed.metadata.model, "gpt-3.5-turbo");
+    }
+
+    #[test]
+    fn test_metadata_slot() {
+        let slot = MetadataSlot::new();
+        let shared = slot.clone();
+        assert_eq!(slot.get(), None);
+
+        shared.set(GenerationMetadata {
+            finish_reason: Some(FinishReason::Stop),
+            ..Default::default()
+        });
+
+        assert_eq!(slot.get().unwrap().finish_reason, Some(FinishReason::Stop));
+        assert_eq!(
+            serde_json::to_value(FinishReason::ContentFilter).unwrap(),
+            "content_filter"
+        );
+    }
+}
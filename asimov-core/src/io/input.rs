@@ -1,4 +1,5 @@
 use crate::error::{AsimovError, Result};
+use crate::io::ContentPart;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -10,6 +11,11 @@ use twox_hash::XxHash64;
 pub trait Input: Send + Sync {
     /// returns a string representation of the value suitable for consumption by a LLM.
     fn render(&self) -> Result<String>;
+    /// returns the text and images of the value, for multimodal models.
+    /// Defaults to the rendered text.
+    fn content(&self) -> Result<Vec<ContentPart>> {
+        Ok(vec![ContentPart::Text(self.render()?)])
+    }
     /// generates a unique hash of the input.
     fn hash(&self) -> Result<u64> {
         let s = &self.render()?;
@@ -40,6 +46,14 @@ impl<T: Input> Input for &T {
     fn render(&self) -> Result<String> {
         (*self).render()
     }
+
+    fn content(&self) -> Result<Vec<ContentPart>> {
+        (*self).content()
+    }
+
+    fn hash(&self) -> Result<u64> {
+        (*self).hash()
+    }
 }
 
 impl<T: Input> Input for Vec<T> {
@@ -149,6 +163,17 @@ macro_rules! prompt {
     };
 }
 
+/// Render a Tera `template` with `variables`. Unlike [`prompt!`], values
+/// are not HTML-escaped and errors are returned instead of panicking.
+pub(crate) fn render_template(template: &str, variables: &[(&str, &str)]) -> Result<String> {
+    let mut context = tera::Context::new();
+    for (name, value) in variables {
+        context.insert(*name, value);
+    }
+    tera::Tera::one_off(template, &context, false)
+        .map_err(|e| AsimovError::Input(format!("Could not render template: {e}")))
+}
+
 impl Embeddable for String {
     type Key = String;
 
@@ -162,6 +187,7 @@ mod tests {
     use super::*;
     use crate::tokenizers::Tokenizer;
     use crate::Embeddable;
+    use asimov_derive::asimov;
     use serde::Deserialize;
 
     #[derive(Serialize, Deserialize, Clone)]
@@ -173,7 +199,7 @@ mod tests {
 
     impl Input for CustomType {
         fn render(&self) -> Result<String> {
-            Ok(self.name.clone())
+            Ok(format!("{}, {}", self.key, self.name))
         }
     }
 
@@ -185,6 +211,13 @@ mod tests {
         }
     }
 
+    #[asimov(key = key)]
+    #[derive(Serialize, Deserialize, Clone)]
+    struct KeyedType {
+        key: String,
+        name: String,
+    }
+
     #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
     struct CustomType2 {
         name: String,
@@ -281,6 +314,18 @@ mod tests {
         assert_eq!(tokenizer.num_tokens(&context).unwrap(), 13);
     }
 
+    #[test]
+    fn test_keyed_input() {
+        let keyed_type = KeyedType {
+            key: "key".to_string(),
+            name: "keyed object".to_string(),
+        };
+
+        let context = prompt!("do this task using: {{keyed_type}}", keyed_type);
+        assert_eq!(context, "do this task using: key, keyed object");
+        assert_eq!(keyed_type.key(), "key");
+    }
+
     #[test]
     fn macro_prompt() {
         let ident1 = "23.4,56.4,56.3,23.4,443.2,456.4,456.2,42.5";
@@ -317,4 +362,95 @@ mod tests {
 
         println!("{}", custom_type.key().render().unwrap());
     }
+
+    #[asimov(key = name)]
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Customer {
+        name: String,
+        #[asimov(label = "Address")]
+        address: String,
+        #[asimov(json)]
+        tags: Vec<String>,
+        #[asimov(skip)]
+        internal_id: String,
+    }
+
+    #[asimov(key = name, style = "lines")]
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Contact {
+        name: String,
+        #[asimov(label = "Phone number")]
+        phone: String,
+        #[asimov(skip)]
+        internal_id: String,
+    }
+
+    #[asimov(key = name, style = "json")]
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Profile {
+        name: String,
+        #[asimov(label = "years")]
+        age: u8,
+        #[asimov(skip)]
+        internal_id: String,
+    }
+
+    #[asimov(key = name, template = "{{ name }} lives at {{ address }}.")]
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Resident {
+        name: String,
+        address: String,
+    }
+
+    #[test]
+    fn test_field_attributes() {
+        let customer = Customer {
+            name: "Ada".to_string(),
+            address: "1234 Main St".to_string(),
+            tags: vec!["vip".to_string()],
+            internal_id: "42".to_string(),
+        };
+
+        assert_eq!(
+            customer.render().unwrap(),
+            r#"Ada, Address: 1234 Main St, ["vip"]"#
+        );
+        assert_eq!(customer.key(), "Ada");
+    }
+
+    #[test]
+    fn test_lines_style() {
+        let contact = Contact {
+            name: "Ada".to_string(),
+            phone: "1234567890".to_string(),
+            internal_id: "42".to_string(),
+        };
+
+        assert_eq!(
+            contact.render().unwrap(),
+            "name: Ada\nPhone number: 1234567890"
+        );
+    }
+
+    #[test]
+    fn test_json_style() {
+        let profile = Profile {
+            name: "Ada".to_string(),
+            age: 36,
+            internal_id: "42".to_string(),
+        };
+
+        let rendered: Value = serde_json::from_str(&profile.render().unwrap()).unwrap();
+        assert_eq!(rendered, serde_json::json!({"name": "Ada", "years": 36}));
+    }
+
+    #[test]
+    fn test_template_style() {
+        let resident = Resident {
+            name: "Ada".to_string(),
+            address: "1234 Main St".to_string(),
+        };
+
+        assert_eq!(resident.render().unwrap(), "Ada lives at 1234 Main St.");
+    }
 }
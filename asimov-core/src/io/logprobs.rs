@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+
+use derive_more::{Deref, DerefMut};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::io::{AsimovOutput, Structured};
+
+/// Log probability of a token considered by the LLM.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f32,
+}
+
+/// Log probability of a generated token, along with the most likely
+/// alternatives at its position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f32,
+    /// Most likely tokens at this position, including the generated one.
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// A generated value along with the log probabilities of its tokens.
+///
+/// Generate `WithLogprobs<T>` instead of `T` to know how confident the LLM
+/// is in its answer, e.g. to flag uncertain classifications. The number of
+/// alternatives per token is set by
+/// [`GenerationOptions::top_logprobs`](crate::models::GenerationOptions::top_logprobs).
+/// It dereferences to the value.
+#[derive(Debug, Clone, PartialEq, Deref, DerefMut, Serialize)]
+pub struct WithLogprobs<T> {
+    #[deref]
+    #[deref_mut]
+    pub value: T,
+    pub tokens: Vec<TokenLogprob>,
+}
+
+impl<T> WithLogprobs<T> {
+    pub fn new(value: T, tokens: Vec<TokenLogprob>) -> Self {
+        Self { value, tokens }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// Probability of the whole answer, as generated.
+    pub fn probability(&self) -> f64 {
+        self.tokens
+            .iter()
+            .map(|t| t.logprob as f64)
+            .sum::<f64>()
+            .exp()
+    }
+
+    /// Probability of each of `labels` being the answer.
+    ///
+    /// The generated tokens are followed, ignoring whitespace and JSON
+    /// quotes, until the answer so far matches a single label. At each
+    /// token, the alternatives count for the labels they could start, in
+    /// proportion to the probability of the tokens before them: e.g. with
+    /// `Ne` (0.6) then `utral` (1.0) generated, `Neutral` gets 0.6. As only
+    /// the generated tokens are followed, alternatives that could start
+    /// several labels are split evenly between them. Labels missing from
+    /// the alternatives have a probability of 0.
+    pub fn label_probabilities(&self, labels: &[&str]) -> BTreeMap<String, f64> {
+        let mut probabilities: BTreeMap<String, f64> = labels
+            .iter()
+            .map(|label| (label.to_string(), 0.0))
+            .collect();
+        let mut add = |labels: Vec<&str>, probability: f64| {
+            let share = probability / labels.len() as f64;
+            for label in labels {
+                *probabilities.get_mut(label).unwrap() += share;
+            }
+        };
+
+        // Answer generated so far, and its probability from its first token
+        // that is not whitespace or a quote.
+        let mut generated = String::new();
+        let mut probability = 1.0;
+
+        for token in &self.tokens {
+            if normalize(&format!("{generated}{}", token.token)).is_empty() {
+                generated.push_str(&token.token);
+                continue;
+            }
+
+            for alternative in &token.top_logprobs {
+                if alternative.token != token.token {
+                    let text = format!("{generated}{}", alternative.token);
+                    let share = probability * (alternative.logprob as f64).exp();
+                    add(matching(labels, &text), share);
+                }
+            }
+
+            generated.push_str(&token.token);
+            probability *= (token.logprob as f64).exp();
+            let candidates = matching(labels, &generated);
+            if candidates.len() <= 1 {
+                add(candidates, probability);
+                break;
+            }
+        }
+        probabilities
+    }
+}
+
+impl<T: AsimovOutput> WithLogprobs<T> {
+    /// Probability of each possible answer, for boolean and enum outputs.
+    /// See [`WithLogprobs::label_probabilities`].
+    pub fn labels(&self) -> BTreeMap<String, f64> {
+        let labels = schema_labels(&T::json_schema());
+        let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+        self.label_probabilities(&labels)
+    }
+}
+
+impl<T: AsimovOutput> WithLogprobs<Structured<T>> {
+    /// Probability of each possible answer, for boolean and enum outputs.
+    /// See [`WithLogprobs::label_probabilities`].
+    pub fn labels(&self) -> BTreeMap<String, f64> {
+        let labels = schema_labels(&T::json_schema());
+        let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+        self.label_probabilities(&labels)
+    }
+}
+
+/// The values allowed by a boolean or enum schema.
+fn schema_labels(schema: &Value) -> Vec<String> {
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        return values
+            .iter()
+            .map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string))
+            .collect();
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some("boolean") => vec!["true".to_string(), "false".to_string()],
+        _ => vec![],
+    }
+}
+
+/// Labels that `text` could start, or that it starts with.
+fn matching<'a>(labels: &[&'a str], text: &str) -> Vec<&'a str> {
+    let text = normalize(text);
+    labels
+        .iter()
+        .copied()
+        .filter(|label| label.starts_with(text) || text.starts_with(label))
+        .collect()
+}
+
+fn normalize(text: &str) -> &str {
+    text.trim().trim_matches('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn token(token: &str, logprob: f32, top: &[(&str, f32)]) -> TokenLogprob {
+        TokenLogprob {
+            token: token.to_string(),
+            logprob,
+            top_logprobs: top
+                .iter()
+                .map(|(token, logprob)| TopLogprob {
+                    token: token.to_string(),
+                    logprob: *logprob,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_boolean_labels() {
+        let answer = WithLogprobs::new(
+            true,
+            vec![token(
+                "true",
+                0.8f32.ln(),
+                &[("true", 0.8f32.ln()), ("false", 0.15f32.ln())],
+            )],
+        );
+        assert!((answer.probability() - 0.8).abs() < 1e-6);
+
+        let labels = answer.labels();
+        assert!((labels["true"] - 0.8).abs() < 1e-6);
+        assert!((labels["false"] - 0.15).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_enum_labels() {
+        let schema = json!({ "type": "string", "enum": ["Positive", "Negative", "Neutral"] });
+        let labels = schema_labels(&schema);
+        let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+
+        let answer = WithLogprobs::new(
+            "Neutral".to_string(),
+            vec![
+                token("\"", 0.0, &[("\"", 0.0)]),
+                token(
+                    "Ne",
+                    0.6f32.ln(),
+                    &[("Ne", 0.6f32.ln()), ("Pos", 0.3f32.ln())],
+                ),
+                token("utral", 0.0, &[("utral", 0.0)]),
+            ],
+        );
+        let probabilities = answer.label_probabilities(&labels);
+        assert!((probabilities["Positive"] - 0.3).abs() < 1e-6);
+        // "Ne" could start both "Negative" and "Neutral": "utral" tells them apart.
+        assert_eq!(probabilities["Negative"], 0.0);
+        assert!((probabilities["Neutral"] - 0.6).abs() < 1e-6);
+
+        // Tokens running past the label, up to the closing quote.
+        let answer = WithLogprobs::new(
+            "Positive".to_string(),
+            vec![
+                token("\"", 0.0, &[("\"", 0.0)]),
+                token(
+                    "Positive\"",
+                    0.7f32.ln(),
+                    &[("Positive\"", 0.7f32.ln()), ("Negative", 0.2f32.ln())],
+                ),
+            ],
+        );
+        let probabilities = answer.label_probabilities(&labels);
+        assert!((probabilities["Positive"] - 0.7).abs() < 1e-6);
+        assert!((probabilities["Negative"] - 0.2).abs() < 1e-6);
+        assert_eq!(probabilities["Neutral"], 0.0);
+    }
+}
@@ -1,5 +1,25 @@
+pub mod candidates;
+pub mod content;
+pub mod control;
+pub mod generation;
 pub mod input;
+pub mod logprobs;
 pub mod output;
+pub mod partial;
+pub mod sanitize;
+pub mod schema;
+#[cfg(feature = "sse")]
+pub mod sse;
 
+pub use candidates::*;
+pub use content::*;
+pub use control::*;
+pub use generation::*;
 pub use input::*;
+pub use logprobs::*;
 pub use output::*;
+pub use partial::*;
+pub use sanitize::*;
+pub use schema::*;
+#[cfg(feature = "sse")]
+pub use sse::*;
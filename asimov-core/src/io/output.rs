@@ -1,36 +1,104 @@
-use crate::error::Result;
+use crate::error::{AsimovError, Result};
+use crate::io::{CancelHandle, GenerationMetadata, MetadataSlot};
 use derive_more::{Deref, DerefMut, Display, From};
 use std::pin::Pin;
-use std::task::Poll;
+use std::task::{Context, Poll};
 
 use futures::Stream;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Serialize};
 
 type ItemStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>; // Ensure the Stream is Send
 
+/// Poll `stream`, unless `cancel` was triggered: the stream is then dropped
+/// and a single [`AsimovError::Cancelled`] is yielded.
+fn poll_cancellable<T>(
+    stream: &mut Option<ItemStream<Result<T>>>,
+    cancel: &CancelHandle,
+    cx: &mut Context<'_>,
+) -> Poll<Option<Result<T>>> {
+    let Some(inner) = stream else {
+        return Poll::Ready(None);
+    };
+
+    cancel.register(cx.waker());
+    if cancel.is_cancelled() {
+        *stream = None;
+        return Poll::Ready(Some(Err(AsimovError::Cancelled)));
+    }
+
+    let item = inner.as_mut().poll_next(cx);
+    if let Poll::Ready(None) = item {
+        *stream = None;
+    }
+    item
+}
+
 /// Building block for streaming LLM responses.
 ///
-/// Holds a stream of tokens generated by the LLM.
+/// Holds a stream of tokens generated by the LLM. Network, API and decoding
+/// errors are yielded as `Err` items, after which the stream ends.
+///
+/// Once the stream is exhausted, [`TokenStream::metadata`] returns the
+/// metadata of the generation, if the backend provides it.
+///
+/// Dropping the stream, cancelling it with [`TokenStream::cancel_handle`] or
+/// ending it with [`TokenStream::timeout`] and [`TokenStream::idle_timeout`]
+/// drops the stream of the backend. For `OpenAiLlm`, this closes the
+/// connection, which stops the generation.
 pub struct TokenStream {
-    stream: ItemStream<String>,
+    stream: Option<ItemStream<Result<String>>>,
+    metadata: MetadataSlot,
+    cancel: CancelHandle,
 }
 
 impl TokenStream {
-    pub fn new(stream: impl Stream<Item = String> + Send + 'static) -> Self {
+    pub fn new(stream: impl Stream<Item = Result<String>> + Send + 'static) -> Self {
+        Self::with_metadata(stream, MetadataSlot::new())
+    }
+
+    /// Create a token stream whose metadata is written to `metadata` by the backend.
+    pub fn with_metadata(
+        stream: impl Stream<Item = Result<String>> + Send + 'static,
+        metadata: MetadataSlot,
+    ) -> Self {
         Self {
-            stream: Box::pin(stream),
+            stream: Some(Box::pin(stream)),
+            metadata,
+            cancel: CancelHandle::new(),
         }
     }
+
+    /// Share the cancellation handle of the stream this one is built on.
+    pub fn with_cancel_handle(mut self, cancel: CancelHandle) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Handle to cancel the stream, e.g. from another task.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Metadata of the generation, available once the stream is exhausted.
+    pub fn metadata(&self) -> Option<GenerationMetadata> {
+        self.metadata.get()
+    }
+
+    /// Slot holding the metadata, to hand over to streams built on top of this one.
+    pub fn metadata_slot(&self) -> MetadataSlot {
+        self.metadata.clone()
+    }
 }
 
 impl Stream for TokenStream {
-    type Item = String;
+    type Item = Result<String>;
 
     fn poll_next(
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.get_mut().stream).poll_next(cx)
+        let this = self.get_mut();
+        poll_cancellable(&mut this.stream, &this.cancel, cx)
     }
 }
 
@@ -75,6 +143,18 @@ impl RawString {
     }
 }
 
+/// Output parsed from the JSON generated by the LLM, like any `Deserialize`
+/// type, except that the format instructions of `T` (see
+/// [`AsimovOutput`](crate::io::AsimovOutput)) are appended to the prompt.
+#[derive(Deref, DerefMut, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Structured<T>(pub T);
+
+impl<T> Structured<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
 /// `StreamedOutput` provides a general abstraction over streamed responses.
 ///
 /// To generate a streamed response, you only need to wrap the result type
@@ -90,18 +170,50 @@ impl RawString {
 /// returns the token stream wrapped in an `Ok`.
 ///
 /// Should you want to access the token stream directly, you should use
-/// [`TokenStream`] as a result type. [`TokenStream`] is a stream of `String`
-/// objects.
+/// [`TokenStream`] as a result type. [`TokenStream`] is a stream of
+/// `Result<String>` items.
+///
+/// Like [`TokenStream`], the metadata of the generation is available through
+/// [`StreamedOutput::metadata`] once the stream is exhausted, and the
+/// generation can be stopped with [`StreamedOutput::cancel_handle`].
 pub struct StreamedOutput<T> {
-    stream: ItemStream<Result<T>>,
+    stream: Option<ItemStream<Result<T>>>,
+    metadata: MetadataSlot,
+    cancel: CancelHandle,
 }
 
 impl<T> StreamedOutput<T> {
     pub fn new(stream: impl Stream<Item = Result<T>> + Send + 'static) -> Self {
+        Self::with_metadata(stream, MetadataSlot::new())
+    }
+
+    /// Create a streamed output whose metadata is written to `metadata` by the backend.
+    pub fn with_metadata(
+        stream: impl Stream<Item = Result<T>> + Send + 'static,
+        metadata: MetadataSlot,
+    ) -> Self {
         Self {
-            stream: Box::pin(stream),
+            stream: Some(Box::pin(stream)),
+            metadata,
+            cancel: CancelHandle::new(),
         }
     }
+
+    /// Share the cancellation handle of the stream this one is built on.
+    pub fn with_cancel_handle(mut self, cancel: CancelHandle) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Handle to cancel the stream, e.g. from another task.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Metadata of the generation, available once the stream is exhausted.
+    pub fn metadata(&self) -> Option<GenerationMetadata> {
+        self.metadata.get()
+    }
 }
 
 impl<T> Stream for StreamedOutput<T> {
@@ -111,6 +223,7 @@ impl<T> Stream for StreamedOutput<T> {
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.get_mut().stream).poll_next(cx)
+        let this = self.get_mut();
+        poll_cancellable(&mut this.stream, &this.cancel, cx)
     }
 }
@@ -0,0 +1,295 @@
+use async_stream::stream;
+use derive_more::{Deref, DerefMut};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::io::{StreamedOutput, TokenStream};
+
+/// Snapshot of a single JSON value while it is being generated.
+///
+/// Generate `StreamedOutput<Partial<D>>` to receive progressively more
+/// complete versions of `D` as tokens arrive. Snapshots are only yielded when
+/// the repaired prefix deserializes into `D`, so fields that have not been
+/// generated yet should be `Option`s or have a `#[serde(default)]`.
+///
+/// The last item is the fully parsed value, with `complete` set to `true`.
+#[derive(Debug, Clone, PartialEq, Deref, DerefMut)]
+pub struct Partial<D> {
+    #[deref]
+    #[deref_mut]
+    pub value: D,
+    /// Whether the whole value has been generated.
+    pub complete: bool,
+}
+
+impl<D: DeserializeOwned + Send + 'static> StreamedOutput<Partial<D>> {
+    /// Stream snapshots of the JSON value generated in `tokens`.
+    ///
+    /// Identical consecutive snapshots are skipped. Token stream errors are
+    /// forwarded and end the stream, and a final value that fails to parse
+    /// is reported as an error.
+    pub fn from_tokens(mut tokens: TokenStream) -> Self {
+        let metadata = tokens.metadata_slot();
+        let cancel = tokens.cancel_handle();
+
+        let s = stream! {
+            let mut buffer = String::new();
+            let mut last: Option<Value> = None;
+
+            while let Some(token) = tokens.next().await {
+                match token {
+                    Ok(token) => buffer.push_str(&token),
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+
+                let Some(value) = repair_json_prefix(&buffer)
+                    .and_then(|repaired| serde_json::from_str::<Value>(&repaired).ok())
+                else {
+                    continue;
+                };
+                if last.as_ref() == Some(&value) {
+                    continue;
+                }
+                if let Ok(parsed) = D::deserialize(&value) {
+                    last = Some(value);
+                    yield Ok(Partial { value: parsed, complete: false });
+                }
+            }
+
+            match serde_json::from_str::<D>(buffer.trim()) {
+                Ok(value) => yield Ok(Partial { value, complete: true }),
+                Err(e) => yield Err(e.into()),
+            }
+        };
+
+        Self::with_metadata(s, metadata).with_cancel_handle(cancel)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Container {
+    /// An object, and whether its next string is a key.
+    Object {
+        expecting_key: bool,
+    },
+    Array,
+}
+
+/// Closes a truncated JSON document so that it can be parsed.
+///
+/// A string value being generated is closed, while an incomplete key,
+/// number or literal is dropped along with what precedes it up to the last
+/// complete value. Returns `None` if the prefix holds no value yet.
+///
+/// General-purpose JSON repair closes whatever is open, turning a
+/// half-generated `tru` or `12` into a wrong value or a parse error; a
+/// snapshot must only ever show values the model has finished, so the
+/// truncation point is tracked here instead.
+pub(crate) fn repair_json_prefix(prefix: &str) -> Option<String> {
+    let mut stack: Vec<Container> = Vec::new();
+    // End of the longest prefix that is valid once closed, and the closers it needs.
+    let mut safe: Option<(usize, String)> = None;
+
+    let mut in_string = false;
+    let mut string_is_key = false;
+    // Start of the escape sequence being read, if any, and the hex digits it still expects.
+    let mut escape: Option<usize> = None;
+    let mut unicode_digits = 0;
+    let mut in_scalar = false;
+
+    let closers = |stack: &[Container]| -> String {
+        stack
+            .iter()
+            .rev()
+            .map(|c| match c {
+                Container::Object { .. } => '}',
+                Container::Array => ']',
+            })
+            .collect()
+    };
+
+    for (i, c) in prefix.char_indices() {
+        if in_string {
+            if unicode_digits > 0 {
+                unicode_digits -= 1;
+                if unicode_digits == 0 {
+                    escape = None;
+                }
+            } else if escape.is_some() {
+                if c == 'u' {
+                    unicode_digits = 4;
+                } else {
+                    escape = None;
+                }
+            } else if c == '\\' {
+                escape = Some(i);
+            } else if c == '"' {
+                in_string = false;
+                if !string_is_key {
+                    safe = Some((i + 1, closers(&stack)));
+                }
+            }
+            continue;
+        }
+
+        if in_scalar {
+            if c.is_whitespace() || matches!(c, ',' | '}' | ']') {
+                in_scalar = false;
+                safe = Some((i, closers(&stack)));
+            } else {
+                continue;
+            }
+        }
+
+        match c {
+            '"' => {
+                in_string = true;
+                string_is_key = matches!(
+                    stack.last(),
+                    Some(Container::Object {
+                        expecting_key: true
+                    })
+                );
+            }
+            '{' | '[' => {
+                stack.push(if c == '{' {
+                    Container::Object {
+                        expecting_key: true,
+                    }
+                } else {
+                    Container::Array
+                });
+                safe = Some((i + 1, closers(&stack)));
+            }
+            '}' | ']' => {
+                stack.pop();
+                safe = Some((i + 1, closers(&stack)));
+            }
+            ':' => {
+                if let Some(Container::Object { expecting_key }) = stack.last_mut() {
+                    *expecting_key = false;
+                }
+            }
+            ',' => {
+                if let Some(Container::Object { expecting_key }) = stack.last_mut() {
+                    *expecting_key = true;
+                }
+            }
+            c if c.is_whitespace() => {}
+            _ => in_scalar = true,
+        }
+    }
+
+    if in_string && !string_is_key {
+        let end = escape.unwrap_or(prefix.len());
+        return Some(format!("{}\"{}", &prefix[..end], closers(&stack)));
+    }
+    if in_scalar && stack.is_empty() {
+        // A top-level scalar is only complete at the end of the document.
+        return Some(prefix.to_string());
+    }
+
+    safe.map(|(end, closers)| format!("{}{}", &prefix[..end], closers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Result, AsimovError};
+    use serde::Deserialize;
+
+    #[test]
+    fn test_repair_json_prefix() {
+        let cases = [
+            (r#"{"code": "import pan"#, r#"{"code": "import pan"}"#),
+            (r#"{"a": [1, 2"#, r#"{"a": [1]}"#),
+            (r#"{"a": [1, 2,"#, r#"{"a": [1, 2]}"#),
+            (r#"{"a": 1, "co"#, r#"{"a": 1}"#),
+            (r#"{"a": 1, "code":"#, r#"{"a": 1}"#),
+            (r#"{"a": tru"#, r#"{}"#),
+            (r#"{"a": "line\"#, r#"{"a": "line"}"#),
+            (r#"{"a": "caf\u00"#, r#"{"a": "caf"}"#),
+            (r#"[{"a": "x"}, {"b""#, r#"[{"a": "x"}, {}]"#),
+            (r#""hello"#, r#""hello""#),
+        ];
+
+        for (prefix, expected) in cases {
+            assert_eq!(repair_json_prefix(prefix).unwrap(), expected, "{prefix}");
+        }
+        assert_eq!(repair_json_prefix("  "), None);
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct PythonCode {
+        #[serde(default)]
+        code: String,
+        language: Option<String>,
+    }
+
+    fn token_stream(tokens: Vec<Result<&'static str>>) -> TokenStream {
+        TokenStream::new(futures::stream::iter(
+            tokens.into_iter().map(|t| t.map(String::from)),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_partial_snapshots() {
+        let tokens = token_stream(vec![
+            Ok(r#"{"code": "import"#),
+            Ok(r#" numpy"#),
+            Ok(r#" as np"#),
+            Ok(r#"", "#),
+            Ok(r#""language": "py"#),
+            Ok(r#"thon"}"#),
+        ]);
+
+        let snapshots: Vec<Partial<PythonCode>> = StreamedOutput::from_tokens(tokens)
+            .map(|s| s.unwrap())
+            .collect()
+            .await;
+
+        let codes: Vec<&str> = snapshots.iter().map(|s| s.code.as_str()).collect();
+        assert_eq!(
+            codes,
+            [
+                "import",
+                "import numpy",
+                "import numpy as np",
+                "import numpy as np",
+                "import numpy as np",
+                "import numpy as np"
+            ]
+        );
+        assert_eq!(snapshots[3].language.as_deref(), Some("py"));
+        assert!(snapshots.iter().rev().skip(1).all(|s| !s.complete));
+
+        let last = snapshots.last().unwrap();
+        assert!(last.complete);
+        assert_eq!(last.language.as_deref(), Some("python"));
+    }
+
+    #[tokio::test]
+    async fn test_partial_errors() {
+        let tokens = token_stream(vec![
+            Ok(r#"{"code": "x"#),
+            Err(AsimovError::Output("connection reset".to_string())),
+            Ok(r#""}"#),
+        ]);
+        let items: Vec<_> = StreamedOutput::<Partial<PythonCode>>::from_tokens(tokens)
+            .collect()
+            .await;
+        assert_eq!(items.len(), 2);
+        assert!(matches!(items[1], Err(AsimovError::Output(_))));
+
+        let truncated = token_stream(vec![Ok(r#"{"code": "x"#)]);
+        let items: Vec<_> = StreamedOutput::<Partial<PythonCode>>::from_tokens(truncated)
+            .collect()
+            .await;
+        assert_eq!(items[0].as_ref().unwrap().code, "x");
+        assert!(matches!(items[1], Err(AsimovError::ParsingError(_))));
+    }
+}
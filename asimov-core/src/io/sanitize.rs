@@ -0,0 +1,282 @@
+use futures::StreamExt;
+use typed_builder::TypedBuilder;
+
+use crate::io::TokenStream;
+
+/// How the raw LLM output is cleaned up before being parsed as a stream of JSON values.
+#[derive(TypedBuilder, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonStreamOptions {
+    /// Drop markdown code fence lines such as ` ```json ` and ` ``` `.
+    #[builder(default = true)]
+    pub strip_fences: bool,
+    /// Drop text between JSON values. Values must then start with `{`, `[`,
+    /// or with `"` at the beginning of a line; other top-level scalars are
+    /// dropped too.
+    #[builder(default = true)]
+    pub skip_prose: bool,
+    /// Yield the elements of a top-level array, instead of the array itself.
+    #[builder(default = false)]
+    pub unwrap_array: bool,
+}
+
+impl Default for JsonStreamOptions {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// Backticks at the beginning of a line, which may open a code fence.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Fence {
+    None,
+    Ticks(usize),
+    /// Skipping the rest of a fence line.
+    Line,
+}
+
+/// Incremental cleanup of LLM output into whitespace-separated JSON values.
+///
+/// Chunks can split values, fences and escape sequences anywhere: feed them
+/// in order to [`JsonSanitizer::push`].
+pub struct JsonSanitizer {
+    options: JsonStreamOptions,
+    /// Nesting depth inside the current value, not counting an unwrapped array.
+    depth: usize,
+    in_string: bool,
+    escape: bool,
+    /// Inside a number or literal at the top level.
+    in_scalar: bool,
+    /// Between the elements of an unwrapped top-level array.
+    in_array: bool,
+    /// Only whitespace has been seen since the last line break outside values.
+    line_start: bool,
+    fence: Fence,
+}
+
+impl JsonSanitizer {
+    pub fn new(options: JsonStreamOptions) -> Self {
+        Self {
+            options,
+            depth: 0,
+            in_string: false,
+            escape: false,
+            in_scalar: false,
+            in_array: false,
+            line_start: true,
+            fence: Fence::None,
+        }
+    }
+
+    /// Clean up the next chunk of output.
+    pub fn push(&mut self, chunk: &str) -> String {
+        let mut out = String::with_capacity(chunk.len());
+        for c in chunk.chars() {
+            self.push_char(c, &mut out);
+        }
+        out
+    }
+
+    fn in_value(&self) -> bool {
+        self.in_string || self.in_scalar || self.depth > 0
+    }
+
+    fn push_char(&mut self, c: char, out: &mut String) {
+        if self.in_value() {
+            return self.push_value_char(c, out);
+        }
+
+        match self.fence {
+            Fence::Line => {
+                if c == '\n' {
+                    self.fence = Fence::None;
+                    self.line_start = true;
+                }
+                return;
+            }
+            Fence::Ticks(n) if c == '`' => {
+                self.fence = Fence::Ticks(n + 1);
+                return;
+            }
+            Fence::Ticks(n) if n >= 3 => {
+                // Skip the rest of the fence line, e.g. the `json` language tag.
+                self.fence = Fence::Line;
+                return self.push_char(c, out);
+            }
+            Fence::Ticks(n) => {
+                // Not a fence after all: the backticks are prose.
+                self.fence = Fence::None;
+                self.line_start = false;
+                if !self.options.skip_prose {
+                    out.push_str(&"`".repeat(n));
+                }
+            }
+            Fence::None => {}
+        }
+
+        if self.in_array {
+            return self.push_array_char(c, out);
+        }
+
+        match c {
+            '\n' => self.line_start = true,
+            c if c.is_whitespace() => {}
+            '`' if self.options.strip_fences && self.line_start => self.fence = Fence::Ticks(1),
+            '[' if self.options.unwrap_array => {
+                self.in_array = true;
+                self.line_start = false;
+            }
+            '{' | '[' => self.start_value(c, out),
+            '"' if !self.options.skip_prose || self.line_start => self.start_value(c, out),
+            _ if self.options.skip_prose => self.line_start = false,
+            _ => self.start_value(c, out),
+        }
+    }
+
+    /// Between the elements of an unwrapped array.
+    fn push_array_char(&mut self, c: char, out: &mut String) {
+        match c {
+            ']' => self.in_array = false,
+            ',' => {}
+            c if c.is_whitespace() => {}
+            c => self.start_value(c, out),
+        }
+    }
+
+    fn start_value(&mut self, c: char, out: &mut String) {
+        self.line_start = false;
+        match c {
+            '{' | '[' => self.depth = 1,
+            '"' => self.in_string = true,
+            _ => self.in_scalar = true,
+        }
+        out.push(c);
+    }
+
+    fn push_value_char(&mut self, c: char, out: &mut String) {
+        if self.in_string {
+            out.push(c);
+            if self.escape {
+                self.escape = false;
+            } else if c == '\\' {
+                self.escape = true;
+            } else if c == '"' {
+                self.in_string = false;
+                self.end_value(out);
+            }
+            return;
+        }
+
+        if self.in_scalar {
+            if c.is_whitespace() || matches!(c, ',' | ']' | '}' | '{' | '[' | '"') {
+                self.in_scalar = false;
+                self.end_value(out);
+                return self.push_char(c, out);
+            }
+            out.push(c);
+            return;
+        }
+
+        out.push(c);
+        match c {
+            '"' => self.in_string = true,
+            '{' | '[' => self.depth += 1,
+            '}' | ']' => {
+                self.depth -= 1;
+                self.end_value(out);
+            }
+            _ => {}
+        }
+    }
+
+    /// Separate top-level values with a line break.
+    fn end_value(&mut self, out: &mut String) {
+        if self.depth == 0 {
+            out.push('\n');
+            self.line_start = true;
+        }
+    }
+}
+
+impl TokenStream {
+    /// Clean up the tokens so that they form whitespace-separated JSON values.
+    ///
+    /// See [`JsonStreamOptions`]. Errors, metadata and cancellation are passed through.
+    pub fn sanitize_json(self, options: JsonStreamOptions) -> TokenStream {
+        let metadata = self.metadata_slot();
+        let cancel = self.cancel_handle();
+        let mut sanitizer = JsonSanitizer::new(options);
+
+        let stream = self
+            .map(move |token| token.map(|t| sanitizer.push(&t)))
+            .filter(|token| futures::future::ready(!matches!(token, Ok(t) if t.is_empty())));
+
+        TokenStream::with_metadata(stream, metadata).with_cancel_handle(cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::Result, io::JsonStream};
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Item {
+        id: u8,
+    }
+
+    /// Sanitize `text` split into chunks of `size` characters, and parse the resulting items.
+    async fn parse(text: &str, size: usize, options: JsonStreamOptions) -> Vec<Result<Item>> {
+        let chars: Vec<char> = text.chars().collect();
+        let chunks: Vec<Result<String>> =
+            chars.chunks(size).map(|c| Ok(c.iter().collect())).collect();
+
+        let tokens = TokenStream::new(futures::stream::iter(chunks)).sanitize_json(options);
+        let bytes = tokens.map(|t| t.map(String::into_bytes));
+        JsonStream::<Item>::new(Box::pin(bytes)).collect().await
+    }
+
+    async fn assert_items(text: &str, options: JsonStreamOptions, expected: &[u8]) {
+        for size in [1, 2, 3, 7, text.len()] {
+            let items: Vec<u8> = parse(text, size, options)
+                .await
+                .into_iter()
+                .map(|item| item.unwrap().id)
+                .collect();
+            assert_eq!(items, expected, "chunk size {size}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fences_and_prose() {
+        let text = "Sure! Here are the items:\n```json\n{\"id\": 1}\n{\"id\": 2}{\"id\": 3}\n```\nLet me know if you need more.";
+        assert_items(text, JsonStreamOptions::default(), &[1, 2, 3]).await;
+    }
+
+    #[tokio::test]
+    async fn test_unwrap_array() {
+        let options = JsonStreamOptions::builder().unwrap_array(true).build();
+
+        let text = "```\n[\n  {\"id\": 1},\n  {\"id\": 2}\n]\n```";
+        assert_items(text, options, &[1, 2]).await;
+
+        let text = "Result: [{\"id\": 1, \"extra\": [1, 2]}, {\"id\": 2, \"note\": \"[x], {y}\"}]";
+        assert_items(text, options, &[1, 2]).await;
+    }
+
+    #[test]
+    fn test_sanitizer_keeps_strings() {
+        let mut sanitizer = JsonSanitizer::new(JsonStreamOptions::default());
+        let out = sanitizer.push("Text with \"quotes\" {\"a\": \"```\\\"}\"}\n\"line\"");
+        assert_eq!(out, "{\"a\": \"```\\\"}\"}\n\"line\"\n");
+
+        let mut sanitizer = JsonSanitizer::new(
+            JsonStreamOptions::builder()
+                .skip_prose(false)
+                .strip_fences(false)
+                .build(),
+        );
+        assert_eq!(sanitizer.push("1 2"), "1\n2");
+        assert_eq!(sanitizer.push(" true"), "\ntrue");
+    }
+}
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, marker::PhantomData};
+
+use serde_json::{json, Value};
+
+use crate::{
+    error::Result,
+    io::{ContentPart, Input},
+};
+
+/// `AsimovOutput` describes the JSON a LLM should produce to be parsed into
+/// the implementing type.
+///
+/// Use `#[derive(AsimovOutput)]` to implement it for your own types: doc
+/// comments on the type and its fields become descriptions in the schema,
+/// and `#[serde(rename, rename_all, default, skip)]` attributes are honored.
+///
+/// Generate [`Structured<T>`](crate::io::Structured) to have the format
+/// instructions appended to the prompt automatically.
+pub trait AsimovOutput {
+    /// JSON schema of the type.
+    fn json_schema() -> Value;
+
+    /// Whether a struct field of this type may be omitted.
+    fn optional() -> bool {
+        false
+    }
+
+    /// Instructions telling the LLM how to format its answer.
+    fn format_instructions() -> String {
+        let schema = Self::json_schema();
+        let mut instructions = format!(
+            "Respond only with {}, without any surrounding text or code fences.",
+            article(&describe_type(&schema))
+        );
+
+        if let Some(description) = schema.get("description").and_then(Value::as_str) {
+            instructions.push_str(&format!("\nIt represents: {description}"));
+        }
+
+        let fields = describe_fields(&schema, 0);
+        if !fields.is_empty() {
+            instructions.push_str("\nFields:\n");
+            instructions.push_str(&fields);
+        }
+
+        instructions.push_str(&format!(
+            "\nJSON schema:\n{}",
+            serde_json::to_string(&schema).unwrap_or_default()
+        ));
+        instructions
+    }
+}
+
+/// Short, human-readable name of the type described by `schema`.
+fn describe_type(schema: &Value) -> String {
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        let values: Vec<String> = values.iter().map(Value::to_string).collect();
+        return format!("one of {}", values.join(", "));
+    }
+
+    match schema.get("type").and_then(Value::as_str) {
+        Some("array") => match schema.get("items") {
+            Some(items) => format!("JSON array of {}", describe_type(items)),
+            None => "JSON array".to_string(),
+        },
+        Some("object") => "JSON object".to_string(),
+        Some(ty) => ty.to_string(),
+        None if schema.get("oneOf").is_some() => "JSON object with a single key".to_string(),
+        None => "JSON value".to_string(),
+    }
+}
+
+fn article(description: &str) -> String {
+    if description.starts_with("one of") {
+        description.to_string()
+    } else if description.starts_with(['a', 'e', 'i', 'o', 'u', 'A', 'E', 'I', 'O', 'U']) {
+        format!("an {description}")
+    } else {
+        format!("a {description}")
+    }
+}
+
+/// Bullet list of the fields of an object schema, recursing into nested objects.
+fn describe_fields(schema: &Value, depth: usize) -> String {
+    let schema = match schema.get("items") {
+        Some(items) if schema.get("type").and_then(Value::as_str) == Some("array") => items,
+        _ => schema,
+    };
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return String::new();
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+
+    let mut lines = String::new();
+    for (name, property) in properties {
+        let mut line = format!(
+            "{}- \"{name}\" ({}{})",
+            "  ".repeat(depth),
+            describe_type(property),
+            if required.contains(&name.as_str()) {
+                ""
+            } else {
+                ", optional"
+            }
+        );
+        if let Some(description) = property.get("description").and_then(Value::as_str) {
+            line.push_str(&format!(": {description}"));
+        }
+        lines.push_str(&line);
+        lines.push('\n');
+        lines.push_str(&describe_fields(property, depth + 1));
+    }
+    lines
+}
+
+macro_rules! impl_asimov_output {
+    ($json_type:literal => $($ty:ty),*) => {
+        $(
+            impl AsimovOutput for $ty {
+                fn json_schema() -> Value {
+                    json!({ "type": $json_type })
+                }
+            }
+        )*
+    };
+}
+
+impl_asimov_output!("string" => String, char);
+impl_asimov_output!("boolean" => bool);
+impl_asimov_output!("integer" => i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+impl_asimov_output!("number" => f32, f64);
+
+impl AsimovOutput for Value {
+    fn json_schema() -> Value {
+        json!({})
+    }
+}
+
+impl<T: AsimovOutput> AsimovOutput for Option<T> {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+impl<T: AsimovOutput> AsimovOutput for Box<T> {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+
+    fn optional() -> bool {
+        T::optional()
+    }
+}
+
+impl<T: AsimovOutput> AsimovOutput for Vec<T> {
+    fn json_schema() -> Value {
+        json!({ "type": "array", "items": T::json_schema() })
+    }
+}
+
+impl<T: AsimovOutput> AsimovOutput for HashMap<String, T> {
+    fn json_schema() -> Value {
+        json!({ "type": "object", "additionalProperties": T::json_schema() })
+    }
+}
+
+/// Input followed by the format instructions of `T`.
+pub struct WithFormatInstructions<I, T> {
+    input: I,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<I: Input, T: AsimovOutput> WithFormatInstructions<I, T> {
+    pub fn new(input: I) -> Self {
+        Self {
+            input,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<I: Input, T: AsimovOutput> Input for WithFormatInstructions<I, T> {
+    fn render(&self) -> Result<String> {
+        Ok(format!(
+            "{}\n\n{}",
+            self.input.render()?,
+            T::format_instructions()
+        ))
+    }
+
+    fn content(&self) -> Result<Vec<ContentPart>> {
+        let mut content = self.input.content()?;
+        content.push(ContentPart::Text(T::format_instructions()));
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Sentiment;
+
+    impl AsimovOutput for Sentiment {
+        fn json_schema() -> Value {
+            json!({
+                "type": "object",
+                "description": "Sentiment of a text.",
+                "properties": {
+                    "sentiment": {
+                        "type": "boolean",
+                        "description": "true represents positive, false is negative."
+                    },
+                    "keywords": { "type": "array", "items": { "type": "string" } }
+                },
+                "required": ["sentiment"]
+            })
+        }
+    }
+
+    #[test]
+    fn test_primitive_schemas() {
+        assert_eq!(String::json_schema(), json!({"type": "string"}));
+        assert_eq!(
+            Vec::<u8>::json_schema(),
+            json!({"type": "array", "items": {"type": "integer"}})
+        );
+        assert_eq!(Option::<f64>::json_schema(), json!({"type": "number"}));
+        assert!(Option::<f64>::optional());
+        assert!(!bool::optional());
+    }
+
+    #[test]
+    fn test_format_instructions() {
+        let instructions = Sentiment::format_instructions();
+
+        assert!(instructions.starts_with("Respond only with a JSON object"));
+        assert!(instructions.contains("It represents: Sentiment of a text."));
+        assert!(instructions
+            .contains("- \"sentiment\" (boolean): true represents positive, false is negative."));
+        assert!(instructions.contains("- \"keywords\" (JSON array of string, optional)"));
+    }
+
+    #[test]
+    fn test_with_format_instructions() {
+        let input = WithFormatInstructions::<_, Sentiment>::new("How was the movie?");
+
+        let rendered = input.render().unwrap();
+
+        assert!(rendered.starts_with("How was the movie?\n\nRespond only with"));
+    }
+}
@@ -0,0 +1,434 @@
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use typed_builder::TypedBuilder;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::{GenerationMetadata, MetadataSlot, StreamedOutput, TokenStream},
+};
+
+/// A single Server-Sent Event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// Event type. Clients treat events without a type as `message`.
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+    /// Reconnection delay requested from the client, in milliseconds.
+    pub retry: Option<u64>,
+}
+
+impl SseEvent {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Frame the event, ready to be written to the response body.
+    ///
+    /// Multi-line data is split over several `data` fields. Line breaks in
+    /// the event type and id, which would break the framing, are removed.
+    pub fn encode(&self) -> Vec<u8> {
+        let single_line = |s: &str| s.replace(['\r', '\n'], "");
+
+        let mut frame = String::new();
+        if let Some(event) = &self.event {
+            frame.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(id) = &self.id {
+            frame.push_str(&format!("id: {}\n", single_line(id).replace('\0', "")));
+        }
+        if let Some(retry) = self.retry {
+            frame.push_str(&format!("retry: {retry}\n"));
+        }
+        for line in self.data.split("\r\n").flat_map(|l| l.split(['\r', '\n'])) {
+            frame.push_str(&format!("data: {line}\n"));
+        }
+        frame.push('\n');
+        frame.into_bytes()
+    }
+}
+
+/// How generations are framed as Server-Sent Events.
+///
+/// Each token or item is sent as an event of type `event`. The stream ends
+/// with a `done_event` carrying the generation metadata as JSON (or `null`),
+/// or with an `error_event` carrying `{"error": "<message>"}`.
+#[derive(TypedBuilder, Debug, Clone, PartialEq, Eq)]
+pub struct SseOptions {
+    /// Type of the events carrying tokens or items, `message` if `None`.
+    #[builder(default, setter(strip_option, into))]
+    pub event: Option<String>,
+    #[builder(default = "done".to_string(), setter(into))]
+    pub done_event: String,
+    #[builder(default = "error".to_string(), setter(into))]
+    pub error_event: String,
+    /// Number the events with sequential ids, starting at 0.
+    #[builder(default = true)]
+    pub ids: bool,
+}
+
+impl Default for SseOptions {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl SseOptions {
+    fn is_data_event(&self, event: &SseEvent) -> bool {
+        event.event.as_deref().unwrap_or("message") == self.event.as_deref().unwrap_or("message")
+    }
+}
+
+/// Frame the items of `items` as events, rendering each one with `render`.
+fn encode_stream<T, S>(
+    mut items: S,
+    options: SseOptions,
+    render: fn(T) -> Result<String>,
+    metadata: impl Fn(&S) -> Option<GenerationMetadata> + Send + 'static,
+) -> impl Stream<Item = Vec<u8>> + Send + 'static
+where
+    T: Send + 'static,
+    S: Stream<Item = Result<T>> + Send + Unpin + 'static,
+{
+    stream! {
+        let mut id = 0;
+        let mut frame = |event: SseEvent| {
+            let event = if options.ids { event.with_id(id.to_string()) } else { event };
+            id += 1;
+            event.encode()
+        };
+
+        while let Some(item) = items.next().await {
+            match item.and_then(render) {
+                Ok(data) => {
+                    let event = SseEvent { event: options.event.clone(), ..SseEvent::new(data) };
+                    yield frame(event);
+                }
+                Err(e) => {
+                    let data = serde_json::json!({ "error": e.to_string() }).to_string();
+                    yield frame(SseEvent::new(data).with_event(&options.error_event));
+                    return;
+                }
+            }
+        }
+
+        let data = serde_json::to_string(&metadata(&items)).unwrap_or_else(|_| "null".into());
+        yield frame(SseEvent::new(data).with_event(&options.done_event));
+    }
+}
+
+impl TokenStream {
+    /// Frame the tokens as Server-Sent Events. See [`SseOptions`].
+    ///
+    /// The stream ends after the first error, which is sent as an error event.
+    pub fn into_sse(self, options: SseOptions) -> impl Stream<Item = Vec<u8>> + Send + 'static {
+        encode_stream(self, options, Ok, TokenStream::metadata)
+    }
+
+    /// Read the tokens from an SSE upstream framed with the same `options`,
+    /// e.g. by [`TokenStream::into_sse`].
+    ///
+    /// Error events are turned into [`AsimovError::Model`], and the metadata
+    /// of the done event is made available through [`TokenStream::metadata`].
+    pub fn from_sse(
+        bytes: impl Stream<Item = Result<Vec<u8>>> + Send + 'static,
+        options: SseOptions,
+    ) -> TokenStream {
+        let slot = MetadataSlot::new();
+        let metadata_slot = slot.clone();
+        let mut events = Box::pin(parse_sse(Box::pin(bytes)));
+
+        let s = stream! {
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+
+                if options.is_data_event(&event) {
+                    yield Ok(event.data);
+                } else if event.event.as_ref() == Some(&options.done_event) {
+                    if let Ok(Some(metadata)) = serde_json::from_str(&event.data) {
+                        metadata_slot.set(metadata);
+                    }
+                    return;
+                } else if event.event.as_ref() == Some(&options.error_event) {
+                    let message = serde_json::from_str::<serde_json::Value>(&event.data)
+                        .ok()
+                        .and_then(|v| v.get("error")?.as_str().map(String::from))
+                        .unwrap_or(event.data);
+                    yield Err(AsimovError::Model(message));
+                    return;
+                }
+            }
+        };
+
+        TokenStream::with_metadata(s, slot)
+    }
+}
+
+impl<D: Serialize + Send + 'static> StreamedOutput<D> {
+    /// Frame the items as Server-Sent Events, with JSON data. See [`SseOptions`].
+    ///
+    /// The stream ends after the first error, which is sent as an error event.
+    pub fn into_sse(self, options: SseOptions) -> impl Stream<Item = Vec<u8>> + Send + 'static {
+        let render = |item: D| Ok(serde_json::to_string(&item)?);
+        encode_stream(self, options, render, StreamedOutput::metadata)
+    }
+}
+
+/// Incremental parser of a Server-Sent Events byte stream.
+///
+/// Chunks can be split anywhere, including inside UTF-8 characters and
+/// `\r\n` line breaks. Follows the [HTML specification](https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation):
+/// comments are ignored, and the last event id carries over to the
+/// following events.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Parse the next chunk, returning the events it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = vec![];
+        let mut start = 0;
+        while let Some(offset) = self.buffer[start..]
+            .iter()
+            .position(|b| *b == b'\n' || *b == b'\r')
+        {
+            let end = start + offset;
+            let next = match self.buffer[end] {
+                b'\r' if end + 1 == self.buffer.len() => break, // May be followed by `\n`.
+                b'\r' if self.buffer[end + 1] == b'\n' => end + 2,
+                _ => end + 1,
+            };
+
+            let line = String::from_utf8_lossy(&self.buffer[start..end]).into_owned();
+            events.extend(self.line(&line));
+            start = next;
+        }
+
+        self.buffer.drain(..start);
+        events
+    }
+
+    fn line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            "retry" => self.retry = value.parse().ok().or(self.retry),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let data = self.data.take()?;
+        Some(SseEvent {
+            event: event.filter(|e| !e.is_empty()),
+            data,
+            id: self.id.clone(),
+            retry: self.retry,
+        })
+    }
+}
+
+/// Parse a Server-Sent Events byte stream into events. See [`SseParser`].
+///
+/// Errors of the byte stream are forwarded and end the stream.
+pub fn parse_sse(
+    mut bytes: impl Stream<Item = Result<Vec<u8>>> + Send + Unpin + 'static,
+) -> impl Stream<Item = Result<SseEvent>> + Send + 'static {
+    stream! {
+        let mut parser = SseParser::new();
+        while let Some(chunk) = bytes.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    for event in parser.push(&chunk) {
+                        yield Ok(event);
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::FinishReason;
+
+    fn tokens(tokens: Vec<Result<&'static str>>) -> TokenStream {
+        let slot = MetadataSlot::new();
+        slot.set(GenerationMetadata {
+            model: "gpt-3.5-turbo".to_string(),
+            finish_reason: Some(FinishReason::Stop),
+            ..Default::default()
+        });
+        TokenStream::with_metadata(
+            futures::stream::iter(tokens.into_iter().map(|t| t.map(String::from))),
+            slot,
+        )
+    }
+
+    async fn encode(stream: impl Stream<Item = Vec<u8>>) -> String {
+        let frames: Vec<Vec<u8>> = stream.collect().await;
+        String::from_utf8(frames.concat()).unwrap()
+    }
+
+    #[test]
+    fn test_encode() {
+        let event = SseEvent::new("line 1\nline 2\r\n")
+            .with_event("token")
+            .with_id("4\n2");
+        assert_eq!(
+            String::from_utf8(event.encode()).unwrap(),
+            "event: token\nid: 42\ndata: line 1\ndata: line 2\ndata: \n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_stream_into_sse() {
+        let sse =
+            encode(tokens(vec![Ok("Hello"), Ok(" world")]).into_sse(Default::default())).await;
+        let frames: Vec<&str> = sse.split("\n\n").collect();
+
+        assert_eq!(frames[0], "id: 0\ndata: Hello");
+        assert_eq!(frames[1], "id: 1\ndata:  world");
+        assert!(frames[2].starts_with("event: done\nid: 2\ndata: {\"model\":\"gpt-3.5-turbo\""));
+
+        let options = SseOptions::builder().event("token").ids(false).build();
+        let failing = tokens(vec![Ok("Hello"), Err(AsimovError::Cancelled), Ok("!")]);
+        assert_eq!(
+            encode(failing.into_sse(options)).await,
+            "event: token\ndata: Hello\n\nevent: error\ndata: {\"error\":\"Generation cancelled\"}\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streamed_output_into_sse() {
+        #[derive(Serialize)]
+        struct Item {
+            id: u8,
+        }
+
+        let items = StreamedOutput::new(futures::stream::iter(vec![Ok(Item { id: 1 })]));
+        let options = SseOptions::builder().ids(false).build();
+        assert_eq!(
+            encode(items.into_sse(options)).await,
+            "data: {\"id\":1}\n\nevent: done\ndata: null\n\n"
+        );
+    }
+
+    #[test]
+    fn test_parser() {
+        let stream = ": comment\r\nevent: token\r\ndata: é\r\ndata\r\nid: 7\r\n\r\ndata:x\r\rretry: 100\nevent: ignored\n\nfield\n\ndata: incomplete";
+
+        for size in [1, 2, 5, stream.len()] {
+            let mut parser = SseParser::new();
+            let events: Vec<SseEvent> = stream
+                .as_bytes()
+                .chunks(size)
+                .flat_map(|chunk| parser.push(chunk))
+                .collect();
+
+            assert_eq!(
+                events,
+                vec![
+                    SseEvent {
+                        event: Some("token".into()),
+                        data: "é\n".into(),
+                        id: Some("7".into()),
+                        retry: None,
+                    },
+                    SseEvent {
+                        event: None,
+                        data: "x".into(),
+                        id: Some("7".into()),
+                        retry: None,
+                    },
+                ],
+                "chunk size {size}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let options = SseOptions::builder().event("token").build();
+
+        let sse = encode(tokens(vec![Ok("a\nb"), Ok("c")]).into_sse(options.clone())).await;
+        let bytes: Vec<Result<Vec<u8>>> =
+            sse.as_bytes().chunks(3).map(|c| Ok(c.to_vec())).collect();
+        let mut stream = TokenStream::from_sse(futures::stream::iter(bytes), options.clone());
+
+        let mut received = vec![];
+        while let Some(token) = stream.next().await {
+            received.push(token.unwrap());
+        }
+        assert_eq!(received, ["a\nb", "c"]);
+        assert_eq!(
+            stream.metadata().unwrap().finish_reason,
+            Some(FinishReason::Stop)
+        );
+
+        let sse =
+            encode(tokens(vec![Ok("a"), Err(AsimovError::Cancelled)]).into_sse(options.clone()))
+                .await;
+        let bytes = futures::stream::iter(vec![Ok(sse.into_bytes())]);
+        let received: Vec<Result<String>> = TokenStream::from_sse(bytes, options).collect().await;
+        assert_eq!(received.len(), 2);
+        assert!(matches!(&received[1], Err(AsimovError::Model(m)) if m == "Generation cancelled"));
+    }
+}
@@ -2,28 +2,50 @@
 //!
 //! High performance LLM I/O.
 
+// Lets the code generated by `asimov_derive` refer to `::asimov` from within this crate.
+extern crate self as asimov;
+
 mod db;
 pub mod error;
 mod io;
 pub mod models;
+pub mod text;
 pub mod tokenizers;
 
 pub mod prelude {
+    pub use crate::db::fewshot::{FewShotSelector, Mmr, Selection, Similarity};
     pub use crate::db::hora::HoraDb;
     pub use crate::db::namespace::Namespace;
     #[cfg(feature = "qdrant")]
     pub use crate::db::qdrant::Qdrant;
     pub use crate::db::space::VectorSpace;
     pub use crate::error::{AsimovError, Result};
+    pub use crate::io::candidates::Candidates;
+    pub use crate::io::content::{ContentPart, Image, ImageDetail, Multimodal};
+    pub use crate::io::control::CancelHandle;
+    pub use crate::io::generation::{FinishReason, Generation, GenerationMetadata, Usage};
+    pub use crate::io::logprobs::{TokenLogprob, TopLogprob, WithLogprobs};
     pub use crate::io::output::*;
-    pub use crate::io::{Embeddable, Input};
+    pub use crate::io::partial::Partial;
+    pub use crate::io::sanitize::JsonStreamOptions;
+    #[cfg(feature = "sse")]
+    pub use crate::io::sse::{parse_sse, SseEvent, SseOptions, SseParser};
+    pub use crate::io::{AsimovOutput, Embeddable, Input};
 
+    #[cfg(feature = "candle")]
+    pub use crate::models::candle::CandleEmbedding;
     #[cfg(feature = "openai")]
     pub use crate::models::openai::*;
-    pub use crate::models::{Embed, Generate};
+    pub use crate::models::{
+        Cached, DiskStore, Embed, Fallback, Generate, GenerateN, GenerationOptions, LlmReranker,
+        LruStore, Majority, Rag, RagAnswer, RateLimited, RateLimiter, RateLimits, Rerank, Retry,
+        RetryPolicy, Router, SelfConsistency,
+    };
+    pub use crate::text::splitter::{Chunk, TextSplitter};
     pub use crate::{lines, prompt};
-    pub use asimov_derive::asimov;
+    pub use asimov_derive::{asimov, AsimovOutput};
     pub use futures::StreamExt;
+    pub use serde_json;
     pub use tera;
 }
 
@@ -0,0 +1,576 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hasher,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_stream::stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use twox_hash::XxHash64;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::{
+        Candidates, Generation, GenerationMetadata, Input, MetadataSlot, RawString, Structured,
+        TokenStream,
+    },
+    models::{Embed, Generate, GenerationOptions},
+};
+
+/// Storage of the responses cached by [`Cached`].
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    async fn get(&self, key: u64) -> Result<Option<String>>;
+    /// Store `value`, which expires after `ttl` if set.
+    async fn set(&self, key: u64, value: String, ttl: Option<Duration>) -> Result<()>;
+}
+
+struct LruEntry {
+    value: String,
+    expires_at: Option<tokio::time::Instant>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<u64, LruEntry>,
+    /// Keys by last use, least recently used first.
+    by_use: BTreeMap<u64, u64>,
+    clock: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, key: u64) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.by_use.remove(&entry.last_used);
+            entry.last_used = self.clock;
+            self.by_use.insert(self.clock, key);
+        }
+    }
+
+    fn remove(&mut self, key: u64) {
+        if let Some(entry) = self.entries.remove(&key) {
+            self.by_use.remove(&entry.last_used);
+        }
+    }
+}
+
+/// In-memory store keeping the `capacity` most recently used responses.
+pub struct LruStore {
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+impl LruStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lru: Default::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for LruStore {
+    async fn get(&self, key: u64) -> Result<Option<String>> {
+        let mut lru = self.lru.lock();
+        let expired = match lru.entries.get(&key) {
+            None => return Ok(None),
+            Some(entry) => entry
+                .expires_at
+                .is_some_and(|t| t <= tokio::time::Instant::now()),
+        };
+
+        if expired {
+            lru.remove(key);
+            return Ok(None);
+        }
+        lru.touch(key);
+        Ok(lru.entries.get(&key).map(|entry| entry.value.clone()))
+    }
+
+    async fn set(&self, key: u64, value: String, ttl: Option<Duration>) -> Result<()> {
+        let mut lru = self.lru.lock();
+        lru.remove(key);
+        while lru.entries.len() >= self.capacity {
+            let Some((_, oldest)) = lru.by_use.pop_first() else {
+                break;
+            };
+            lru.entries.remove(&oldest);
+        }
+        if self.capacity == 0 {
+            return Ok(());
+        }
+
+        let entry = LruEntry {
+            value,
+            expires_at: ttl.map(|ttl| tokio::time::Instant::now() + ttl),
+            last_used: 0,
+        };
+        lru.entries.insert(key, entry);
+        lru.touch(key);
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    /// Expiration time, in seconds since the Unix epoch.
+    expires_at: Option<u64>,
+    value: String,
+}
+
+/// On-disk store, with one JSON file per response in `dir`. Responses are
+/// kept across runs until they expire.
+pub struct DiskStore {
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    /// Store the responses in `dir`, which is created if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| AsimovError::Cache(e.to_string()))?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.json"))
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+#[async_trait]
+impl CacheStore for DiskStore {
+    async fn get(&self, key: u64) -> Result<Option<String>> {
+        let path = self.path(key);
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(AsimovError::Cache(e.to_string())),
+        };
+
+        let entry: DiskEntry = serde_json::from_slice(&content)?;
+        if entry.expires_at.is_some_and(|t| t <= Self::now()) {
+            tokio::fs::remove_file(&path).await.ok();
+            return Ok(None);
+        }
+        Ok(Some(entry.value))
+    }
+
+    async fn set(&self, key: u64, value: String, ttl: Option<Duration>) -> Result<()> {
+        let entry = DiskEntry {
+            expires_at: ttl.map(|ttl| Self::now() + ttl.as_secs()),
+            value,
+        };
+        tokio::fs::write(self.path(key), serde_json::to_vec(&entry)?)
+            .await
+            .map_err(|e| AsimovError::Cache(e.to_string()))
+    }
+}
+
+/// Output that can be stored in a [`CacheStore`].
+pub trait Cacheable: Sized {
+    /// Name of the cached representation, part of the cache key. Outputs of
+    /// the same kind share their entries: e.g. all serde types are `json`.
+    const KIND: &'static str;
+
+    fn to_cache(&self) -> Result<String>;
+    fn from_cache(cached: String) -> Result<Self>;
+}
+
+impl<T: Serialize + DeserializeOwned> Cacheable for T {
+    const KIND: &'static str = "json";
+
+    fn to_cache(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    fn from_cache(cached: String) -> Result<Self> {
+        Ok(serde_json::from_str(&cached)?)
+    }
+}
+
+impl Cacheable for RawString {
+    const KIND: &'static str = "raw";
+
+    fn to_cache(&self) -> Result<String> {
+        Ok(self.0.clone())
+    }
+
+    fn from_cache(cached: String) -> Result<Self> {
+        Ok(RawString::new(cached))
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Cacheable for Structured<T> {
+    const KIND: &'static str = "structured";
+
+    fn to_cache(&self) -> Result<String> {
+        self.0.to_cache()
+    }
+
+    fn from_cache(cached: String) -> Result<Self> {
+        Ok(Structured(T::from_cache(cached)?))
+    }
+}
+
+impl<T: Cacheable> Cacheable for Candidates<T> {
+    const KIND: &'static str = "candidates";
+
+    fn to_cache(&self) -> Result<String> {
+        let values = self.iter().map(T::to_cache).collect::<Result<Vec<_>>>()?;
+        values.to_cache()
+    }
+
+    fn from_cache(cached: String) -> Result<Self> {
+        let values = Vec::<String>::from_cache(cached)?;
+        Ok(Candidates(
+            values
+                .into_iter()
+                .map(T::from_cache)
+                .collect::<Result<_>>()?,
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedGeneration {
+    value: String,
+    metadata: GenerationMetadata,
+}
+
+impl<T: Cacheable> Cacheable for Generation<T> {
+    const KIND: &'static str = "generation";
+
+    fn to_cache(&self) -> Result<String> {
+        CachedGeneration {
+            value: self.value.to_cache()?,
+            metadata: self.metadata.clone(),
+        }
+        .to_cache()
+    }
+
+    fn from_cache(cached: String) -> Result<Self> {
+        let cached = CachedGeneration::from_cache(cached)?;
+        Ok(Generation::new(
+            T::from_cache(cached.value)?,
+            cached.metadata,
+        ))
+    }
+}
+
+/// Version of the cache keys, bumped whenever their layout changes so that
+/// the entries cached by older versions are not read back.
+const KEY_VERSION: u32 = 1;
+
+/// Settings of a model that its responses depend on, e.g. the model name and
+/// the sampling parameters, keying its responses in a [`Cached`] store.
+///
+/// Changing the config invalidates the responses cached so far: leave out
+/// settings that don't change the responses, such as timeouts or retries.
+pub trait CacheConfig {
+    fn cache_config(&self) -> serde_json::Value;
+}
+
+/// Everything a cached response depends on, hashed into its key.
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    version: u32,
+    config: &'a serde_json::Value,
+    options: &'a GenerationOptions,
+    kind: &'a str,
+    input: u64,
+}
+
+/// Cache the responses of a model, so that repeated inputs cost nothing.
+///
+/// Responses are keyed on the [`CacheConfig`] of the model, the generation
+/// options, the [`Cacheable::KIND`] of the output and [`Input::hash`], so
+/// that keys are stable across builds and `DiskStore` entries can be shared.
+/// Streaming callers asking for a [`TokenStream`] get the cached tokens
+/// replayed; a stream is only cached once it completed without error.
+///
+/// ```ignore
+/// let llm = Cached::new(OpenAiLlm::default(), DiskStore::new(".cache/llm")?)
+///     .ttl(Duration::from_secs(24 * 3600));
+/// ```
+pub struct Cached<M, S = LruStore> {
+    model: M,
+    store: Arc<S>,
+    ttl: Option<Duration>,
+    config: serde_json::Value,
+}
+
+impl<M: CacheConfig, S: CacheStore> Cached<M, S> {
+    pub fn new(model: M, store: S) -> Self {
+        Self {
+            config: model.cache_config(),
+            model,
+            store: Arc::new(store),
+            ttl: None,
+        }
+    }
+
+    /// Expire the responses cached from now on after `ttl`.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    fn key(
+        &self,
+        kind: &str,
+        input: &(impl Input + ?Sized),
+        options: &GenerationOptions,
+    ) -> Result<u64> {
+        let key = CacheKey {
+            version: KEY_VERSION,
+            config: &self.config,
+            options,
+            kind,
+            input: input.hash()?,
+        };
+        let mut h = XxHash64::default();
+        h.write(&serde_json::to_vec(&key)?);
+        Ok(h.finish())
+    }
+
+    /// Cache lookup. Failures of the store are logged and treated as misses.
+    async fn get(&self, key: u64) -> Option<String> {
+        self.store
+            .get(key)
+            .await
+            .inspect_err(|e| tracing::warn!("Cache lookup failed: {e}"))
+            .ok()
+            .flatten()
+    }
+}
+
+/// Cache a response. Failures are logged, since the response itself is fine.
+async fn store<S: CacheStore>(store: &S, key: u64, value: Result<String>, ttl: Option<Duration>) {
+    if let Err(e) = async { store.set(key, value?, ttl).await }.await {
+        tracing::warn!("Caching the response failed: {e}");
+    }
+}
+
+#[async_trait]
+impl<T, M, S> Generate<T> for Cached<M, S>
+where
+    T: Cacheable + Send,
+    M: Generate<T> + CacheConfig + Send + Sync,
+    S: CacheStore,
+{
+    async fn generate_with(&self, input: impl Input, options: &GenerationOptions) -> Result<T> {
+        let key = self.key(T::KIND, &input, options)?;
+        if let Some(cached) = self.get(key).await {
+            match T::from_cache(cached) {
+                Ok(output) => return Ok(output),
+                Err(e) => tracing::warn!("Invalid cached response: {e}"),
+            }
+        }
+
+        let output = self.model.generate_with(input, options).await?;
+        store(self.store.as_ref(), key, output.to_cache(), self.ttl).await;
+        Ok(output)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedTokens {
+    tokens: Vec<String>,
+    metadata: Option<GenerationMetadata>,
+}
+
+#[async_trait]
+impl<M, S> Generate<TokenStream> for Cached<M, S>
+where
+    M: Generate<TokenStream> + CacheConfig + Send + Sync,
+    S: CacheStore + 'static,
+{
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        let key = self.key("tokens", &input, options)?;
+        if let Some(cached) = self.get(key).await {
+            match CachedTokens::from_cache(cached) {
+                Ok(cached) => {
+                    let metadata = MetadataSlot::new();
+                    if let Some(m) = cached.metadata {
+                        metadata.set(m);
+                    }
+                    let tokens = futures::stream::iter(cached.tokens.into_iter().map(Ok));
+                    return Ok(TokenStream::with_metadata(tokens, metadata));
+                }
+                Err(e) => tracing::warn!("Invalid cached response: {e}"),
+            }
+        }
+
+        let mut tokens = self.model.generate_with(input, options).await?;
+        let metadata = tokens.metadata_slot();
+        let cancel = tokens.cancel_handle();
+        let cache = self.store.clone();
+        let ttl = self.ttl;
+
+        let s = stream! {
+            let mut seen = vec![];
+            while let Some(token) = tokens.next().await {
+                match token {
+                    Ok(token) => {
+                        seen.push(token.clone());
+                        yield Ok(token);
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+
+            let cached = CachedTokens { tokens: seen, metadata: tokens.metadata() };
+            store(cache.as_ref(), key, cached.to_cache(), ttl).await;
+        };
+
+        Ok(TokenStream::with_metadata(s, metadata).with_cancel_handle(cancel))
+    }
+}
+
+#[async_trait]
+impl<M, S> Embed for Cached<M, S>
+where
+    M: Embed + CacheConfig,
+    S: CacheStore,
+{
+    type Tokenizer = M::Tokenizer;
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    async fn embed<I: Input + ?Sized>(&self, input: &I) -> Result<Vec<f32>> {
+        let key = self.key("embedding", input, &GenerationOptions::default())?;
+        if let Some(cached) = self.get(key).await {
+            match Vec::<f32>::from_cache(cached) {
+                Ok(embedding) => return Ok(embedding),
+                Err(e) => tracing::warn!("Invalid cached embedding: {e}"),
+            }
+        }
+
+        let embedding = self.model.embed(input).await?;
+        store(self.store.as_ref(), key, embedding.to_cache(), self.ttl).await;
+        Ok(embedding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::mocks::Echo;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cached_generation() -> Result<()> {
+        let llm = Cached::new(Echo::default(), LruStore::new(10)).ttl(Duration::from_secs(60));
+
+        let first: RawString = llm.generate("Hello").await?;
+        let second: RawString = llm.generate("Hello").await?;
+        assert_eq!(first, second);
+        assert_eq!(llm.model().calls(), 1);
+
+        let _: RawString = llm.generate("Hi").await?;
+        assert_eq!(llm.model().calls(), 2);
+
+        // Keyed on the generation options too.
+        let creative = GenerationOptions::builder().temperature(1.0).build();
+        let _: RawString = llm.generate_with("Hi", &creative).await?;
+        let _: RawString = llm.generate_with("Hi", &creative).await?;
+        assert_eq!(llm.model().calls(), 3);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let _: RawString = llm.generate("Hello").await?;
+        assert_eq!(llm.model().calls(), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cached_token_stream() -> Result<()> {
+        let llm = Cached::new(Echo::default(), LruStore::new(10));
+
+        let tokens: TokenStream = llm.generate("Hello big world").await?;
+        let first: Vec<String> = tokens.map(|t| t.unwrap()).collect().await;
+
+        let tokens: TokenStream = llm.generate("Hello big world").await?;
+        let replayed: Vec<String> = tokens.map(|t| t.unwrap()).collect().await;
+
+        assert_eq!(first, ["Hello ", "big ", "world"]);
+        assert_eq!(replayed, first);
+        assert_eq!(llm.model().calls(), 1);
+
+        // Not shared with other output types.
+        let _: RawString = llm.generate("Hello big world").await?;
+        assert_eq!(llm.model().calls(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stable_key() -> Result<()> {
+        let llm = Cached::new(Echo::default(), LruStore::new(10));
+        let options = GenerationOptions::default();
+        // Cached entries must survive upgrades: bump `KEY_VERSION` instead of
+        // changing this value.
+        let key = llm.key("raw", &"Hello", &options)?;
+        assert_eq!(key, 11784849797600195965);
+        assert_ne!(llm.key("json", &"Hello", &options)?, key);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lru_store() -> Result<()> {
+        let store = LruStore::new(2);
+        store.set(1, "one".into(), None).await?;
+        store.set(2, "two".into(), None).await?;
+        assert_eq!(store.get(1).await?.as_deref(), Some("one"));
+
+        store.set(3, "three".into(), None).await?;
+        assert_eq!(store.get(2).await?, None);
+        assert_eq!(store.get(1).await?.as_deref(), Some("one"));
+        assert_eq!(store.get(3).await?.as_deref(), Some("three"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disk_store() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("asimov-cache-{}", uuid::Uuid::new_v4()));
+        let store = DiskStore::new(&dir)?;
+
+        store.set(1, "one".into(), None).await?;
+        store.set(2, "two".into(), Some(Duration::ZERO)).await?;
+        assert_eq!(store.get(1).await?.as_deref(), Some("one"));
+        assert_eq!(store.get(2).await?, None);
+        assert_eq!(store.get(3).await?, None);
+
+        // Persisted across instances.
+        let store = DiskStore::new(&dir)?;
+        assert_eq!(store.get(1).await?.as_deref(), Some("one"));
+
+        std::fs::remove_dir_all(dir).ok();
+        Ok(())
+    }
+}
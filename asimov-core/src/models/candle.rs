@@ -0,0 +1,212 @@
+use std::{path::Path, sync::Arc};
+
+use ::tokenizers::{Tokenizer as HfTokenizerModel, TruncationParams};
+use async_trait::async_trait;
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+
+use crate::{
+    error::{AsimovError, Result},
+    io::Input,
+    models::Embed,
+    tokenizers::huggingface::HfTokenizer,
+};
+
+/// Sentence-transformer model run locally on CPU, e.g. `all-MiniLM-L6-v2`.
+///
+/// Loads a BERT model directory as downloaded from the HuggingFace hub:
+/// `config.json`, `tokenizer.json` and `model.safetensors`. No API key nor
+/// network access is needed, which makes it suitable for air-gapped
+/// deployments and tests.
+///
+/// ```ignore
+/// let embedder = CandleEmbedding::from_dir("models/all-MiniLM-L6-v2")?;
+/// let mut db = HoraDb::new(embedder);
+/// ```
+///
+/// Embeddings are the mean of the token embeddings, normalized to unit
+/// length like sentence-transformers does. Inputs longer than the model
+/// context are truncated. Inference runs on tokio's blocking thread pool,
+/// so that it doesn't stall the other tasks of the runtime. Clones share
+/// the model.
+#[derive(Clone)]
+pub struct CandleEmbedding {
+    model: Arc<BertModel>,
+    tokenizer: Arc<HfTokenizerModel>,
+    device: Device,
+    dim: usize,
+    normalize: bool,
+}
+
+impl CandleEmbedding {
+    /// Load the model from the directory at `path`.
+    pub fn from_dir(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let device = Device::Cpu;
+
+        let config = std::fs::read_to_string(path.join("config.json"))
+            .map_err(|e| AsimovError::Model(format!("{}: {e}", path.display())))?;
+        let config: Config = serde_json::from_str(&config)?;
+
+        let mut tokenizer =
+            HfTokenizerModel::from_file(path.join("tokenizer.json")).map_err(|e| {
+                AsimovError::Tokenizer(format!(
+                    "Could not load tokenizer from {}: {e}",
+                    path.display()
+                ))
+            })?;
+        tokenizer.with_padding(None);
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(|e| AsimovError::Tokenizer(e.to_string()))?;
+
+        let weights = candle_core::safetensors::load(path.join("model.safetensors"), &device)
+            .map_err(model_error)?;
+        let vb = VarBuilder::from_tensors(weights, DTYPE, &device);
+        let model = BertModel::load(vb, &config).map_err(model_error)?;
+
+        Ok(Self {
+            model: Arc::new(model),
+            tokenizer: Arc::new(tokenizer),
+            device,
+            dim: config.hidden_size,
+            normalize: true,
+        })
+    }
+
+    /// Keep the raw mean of the token embeddings instead of normalizing it.
+    pub fn with_normalization(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Tokenizer of the model, e.g. to split texts into chunks that fit
+    /// its context.
+    pub fn tokenizer(&self) -> HfTokenizer {
+        HfTokenizerModel::clone(&self.tokenizer).into()
+    }
+
+    fn forward(&self, text: String) -> candle_core::Result<Vec<f32>> {
+        let encoding = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(candle_core::Error::wrap)?;
+
+        let ids = Tensor::new(encoding.get_ids(), &self.device)?.unsqueeze(0)?;
+        let type_ids = ids.zeros_like()?;
+        let mask = Tensor::new(encoding.get_attention_mask(), &self.device)?.unsqueeze(0)?;
+        let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+
+        // Mean pooling over the tokens that are not padding.
+        let mask = mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+        let sum = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let mut embedding = sum.broadcast_div(&mask.sum(1)?)?;
+
+        if self.normalize {
+            let norm = embedding.sqr()?.sum_keepdim(1)?.sqrt()?;
+            embedding = embedding.broadcast_div(&norm)?;
+        }
+        embedding.squeeze(0)?.to_vec1()
+    }
+}
+
+fn model_error(e: candle_core::Error) -> AsimovError {
+    AsimovError::Model(e.to_string())
+}
+
+#[async_trait]
+impl Embed for CandleEmbedding {
+    type Tokenizer = HfTokenizer;
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    async fn embed<I: Input + ?Sized>(&self, input: &I) -> Result<Vec<f32>> {
+        let text = input.render()?;
+        let embedder = self.clone();
+        tokio::task::spawn_blocking(move || embedder.forward(text))
+            .await
+            .map_err(|e| AsimovError::Model(e.to_string()))?
+            .map_err(model_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ::tokenizers::{models::wordlevel::WordLevel, pre_tokenizers::whitespace::Whitespace};
+    use candle_nn::VarMap;
+
+    use super::*;
+
+    /// Write a tiny randomly initialized BERT model to a temporary directory.
+    fn tiny_model() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("asimov-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+
+        let config = serde_json::json!({
+            "vocab_size": 5,
+            "hidden_size": 8,
+            "num_hidden_layers": 1,
+            "num_attention_heads": 2,
+            "intermediate_size": 16,
+            "hidden_act": "gelu",
+            "hidden_dropout_prob": 0.0,
+            "max_position_embeddings": 16,
+            "type_vocab_size": 2,
+            "initializer_range": 0.02,
+            "layer_norm_eps": 1e-12,
+            "pad_token_id": 0,
+            "classifier_dropout": null,
+            "model_type": "bert"
+        });
+        std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DTYPE, &Device::Cpu);
+        BertModel::load(vb, &serde_json::from_value(config).unwrap()).unwrap();
+        varmap.save(dir.join("model.safetensors")).unwrap();
+
+        let vocab: HashMap<String, u32> =
+            [("[UNK]", 0), ("this", 1), ("is", 2), ("a", 3), ("test", 4)]
+                .into_iter()
+                .map(|(token, id)| (token.to_string(), id))
+                .collect();
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap();
+        let mut tokenizer = HfTokenizerModel::new(model);
+        tokenizer.with_pre_tokenizer(Whitespace {});
+        tokenizer.save(dir.join("tokenizer.json"), false).unwrap();
+
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_candle_embedding() -> Result<()> {
+        let dir = tiny_model();
+        let embedder = CandleEmbedding::from_dir(&dir)?;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(embedder.dim(), 8);
+        let embedding = embedder.embed(&"this is a test").await?;
+        assert_eq!(embedding.len(), 8);
+        let norm: f32 = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-4);
+
+        // Longer than the model context.
+        let long = "test ".repeat(100);
+        assert_eq!(embedder.embed(&long).await?.len(), 8);
+
+        assert!(CandleEmbedding::from_dir("does/not/exist").is_err());
+        Ok(())
+    }
+}
@@ -5,7 +5,9 @@ use async_trait::async_trait;
 #[async_trait]
 pub trait Embed: Send + Sync {
     type Tokenizer: Tokenizer;
-    const DIM: u32;
+
+    /// Length of the embeddings returned by [`Embed::embed`].
+    fn dim(&self) -> usize;
 
     /// Embed an object that implements the [`Embeddable`] trait.
     async fn embed<I: Input + ?Sized>(&self, input: &I) -> Result<Vec<f32>>;
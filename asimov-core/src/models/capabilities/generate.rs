@@ -1,5 +1,6 @@
 use crate::error::Result;
-use crate::io::Input;
+use crate::io::{Candidates, Input};
+use crate::models::GenerationOptions;
 // use crate::io::StreamedOutput;
 
 use async_trait::async_trait;
@@ -14,5 +15,35 @@ use async_trait::async_trait;
 
 #[async_trait]
 pub trait Generate<T = String> {
-    async fn generate(&self, input: impl Input) -> Result<T>;
+    /// Generate with the settings of the model.
+    async fn generate(&self, input: impl Input) -> Result<T>
+    where
+        Self: Sync,
+    {
+        self.generate_with(input, &GenerationOptions::default())
+            .await
+    }
+
+    /// Generate with `options` overriding the settings of the model for
+    /// this call only. Settings that a model does not support are ignored.
+    async fn generate_with(&self, input: impl Input, options: &GenerationOptions) -> Result<T>;
+}
+
+/// Generate `n` answers to the same input, for models generating
+/// [`Candidates`].
+#[async_trait]
+pub trait GenerateN<T> {
+    async fn generate_n(&self, input: impl Input, n: u8) -> Result<Vec<T>>;
+}
+
+#[async_trait]
+impl<T, M> GenerateN<T> for M
+where
+    T: Send,
+    M: Generate<Candidates<T>> + Sync,
+{
+    async fn generate_n(&self, input: impl Input, n: u8) -> Result<Vec<T>> {
+        let options = GenerationOptions::builder().n(n).build();
+        Ok(self.generate_with(input, &options).await?.into_inner())
+    }
 }
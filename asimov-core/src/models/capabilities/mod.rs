@@ -1,5 +1,7 @@
 mod embed;
 mod generate;
+mod rerank;
 
 pub use embed::*;
 pub use generate::*;
+pub use rerank::*;
@@ -0,0 +1,59 @@
+use crate::{
+    error::{AsimovError, Result},
+    Input,
+};
+use async_trait::async_trait;
+
+/// Trait that defines the behavior of reranking models, which score how
+/// relevant documents are to a query more precisely than embeddings.
+///
+/// Any `Fn(&str, &[String]) -> Result<Vec<f32>>` is a reranker, e.g. to plug
+/// a local cross-encoder.
+#[async_trait]
+pub trait Rerank: Send + Sync {
+    /// Relevance of each of `documents` to `query`, in the same order.
+    /// Higher is more relevant.
+    async fn score<Q, D>(&self, query: &Q, documents: &[D]) -> Result<Vec<f32>>
+    where
+        Q: Input + ?Sized,
+        D: Input;
+
+    /// The `k` most relevant of `documents`, most relevant first. Documents
+    /// scored the same keep their order.
+    async fn rerank<Q, D>(&self, query: &Q, documents: Vec<D>, k: usize) -> Result<Vec<D>>
+    where
+        Q: Input + ?Sized,
+        D: Input,
+    {
+        let scores = self.score(query, &documents).await?;
+        if scores.len() != documents.len() {
+            return Err(AsimovError::Model(format!(
+                "Expected {} scores, got {}",
+                documents.len(),
+                scores.len()
+            )));
+        }
+
+        let mut scored: Vec<(f32, D)> = scores.into_iter().zip(documents).collect();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        Ok(scored.into_iter().take(k).map(|(_, d)| d).collect())
+    }
+}
+
+#[async_trait]
+impl<F> Rerank for F
+where
+    F: Fn(&str, &[String]) -> Result<Vec<f32>> + Send + Sync,
+{
+    async fn score<Q, D>(&self, query: &Q, documents: &[D]) -> Result<Vec<f32>>
+    where
+        Q: Input + ?Sized,
+        D: Input,
+    {
+        let documents = documents
+            .iter()
+            .map(Input::render)
+            .collect::<Result<Vec<_>>>()?;
+        self(&query.render()?, &documents)
+    }
+}
@@ -0,0 +1,185 @@
+use async_trait::async_trait;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::{text_content, Input},
+    models::{CacheConfig, Generate, GenerationOptions},
+    tokenizers::{openai::OpenAiTiktoken, Tokenizer},
+};
+
+/// Try `primary`, and `fallback` if it fails.
+///
+/// Chain more models with [`Fallback::or`], e.g. gpt-4, then gpt-3.5, then a
+/// local model. If every model fails, their errors are reported together in
+/// [`AsimovError::Fallback`], in order.
+///
+/// For streamed outputs, such as [`TokenStream`](crate::io::TokenStream),
+/// the next model is only tried if the stream could not be started: errors
+/// happening while the tokens are streamed are passed to the consumer.
+#[derive(Debug, Clone)]
+pub struct Fallback<A, B> {
+    pub primary: A,
+    pub fallback: B,
+}
+
+impl<A, B> Fallback<A, B> {
+    pub fn new(primary: A, fallback: B) -> Self {
+        Self { primary, fallback }
+    }
+
+    /// Try `model` when every model so far failed.
+    pub fn or<C>(self, model: C) -> Fallback<Self, C> {
+        Fallback::new(self, model)
+    }
+}
+
+/// Errors of each model tried, flattening nested fallbacks.
+fn errors(error: AsimovError) -> Vec<AsimovError> {
+    match error {
+        AsimovError::Fallback(errors) => errors,
+        error => vec![error],
+    }
+}
+
+#[async_trait]
+impl<T, A, B> Generate<T> for Fallback<A, B>
+where
+    T: Send,
+    A: Generate<T> + Send + Sync,
+    B: Generate<T> + Send + Sync,
+{
+    async fn generate_with(&self, input: impl Input, options: &GenerationOptions) -> Result<T> {
+        let primary_error = match self.primary.generate_with(&input, options).await {
+            Ok(output) => return Ok(output),
+            Err(e) => e,
+        };
+        tracing::warn!("Model failed, falling back to the next one: {primary_error}");
+
+        self.fallback
+            .generate_with(&input, options)
+            .await
+            .map_err(|e| {
+                let mut all = errors(primary_error);
+                all.extend(errors(e));
+                AsimovError::Fallback(all)
+            })
+    }
+}
+
+impl<A: CacheConfig, B: CacheConfig> CacheConfig for Fallback<A, B> {
+    fn cache_config(&self) -> serde_json::Value {
+        serde_json::json!([self.primary.cache_config(), self.fallback.cache_config()])
+    }
+}
+
+/// Send each prompt to `first` or `second`, depending on the text of the
+/// prompt.
+///
+/// ```ignore
+/// // Use the larger context window only when needed.
+/// let llm = Router::by_tokens(gpt35, gpt35_16k, 3000);
+/// ```
+pub struct Router<A, B> {
+    pub first: A,
+    pub second: B,
+    use_first: Box<dyn Fn(&str) -> bool + Send + Sync>,
+}
+
+impl<A, B> Router<A, B> {
+    /// Route the prompts for which `use_first` returns `true` to `first`.
+    pub fn new(
+        first: A,
+        second: B,
+        use_first: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            use_first: Box::new(use_first),
+        }
+    }
+
+    /// Route the prompts of at most `max_tokens` tokens to `short`, and the
+    /// others to `long`. Tokens are counted with [`OpenAiTiktoken`].
+    pub fn by_tokens(short: A, long: B, max_tokens: usize) -> Self {
+        Self::by_tokens_with(short, long, max_tokens, OpenAiTiktoken::new())
+    }
+
+    /// Like [`Router::by_tokens`], counting tokens with `tokenizer`.
+    pub fn by_tokens_with(
+        short: A,
+        long: B,
+        max_tokens: usize,
+        tokenizer: impl Tokenizer + 'static,
+    ) -> Self {
+        Self::new(short, long, move |prompt| {
+            tokenizer.length(prompt) <= max_tokens
+        })
+    }
+}
+
+#[async_trait]
+impl<T, A, B> Generate<T> for Router<A, B>
+where
+    T: Send,
+    A: Generate<T> + Send + Sync,
+    B: Generate<T> + Send + Sync,
+{
+    async fn generate_with(&self, input: impl Input, options: &GenerationOptions) -> Result<T> {
+        let prompt = text_content(&input.content()?);
+        if (self.use_first)(&prompt) {
+            self.first.generate_with(input, options).await
+        } else {
+            self.second.generate_with(input, options).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::{RawString, TokenStream},
+        models::mocks::{Echo, Failing},
+    };
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_fallback() -> Result<()> {
+        let llm = Fallback::new(Failing, Echo::named("second")).or(Echo::named("third"));
+
+        let answer: RawString = llm.generate("Hi").await?;
+        assert_eq!(answer.0, "second: Hi");
+
+        let tokens: TokenStream = llm.generate("Hi").await?;
+        let tokens: Vec<String> = tokens.map(|t| t.unwrap()).collect().await;
+        assert_eq!(tokens.concat(), "second: Hi");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fallback_errors() {
+        let llm = Fallback::new(Failing, Failing).or(Failing);
+
+        let answer: Result<RawString> = llm.generate("Hi").await;
+        let Err(AsimovError::Fallback(errors)) = answer else {
+            panic!("expected a fallback error");
+        };
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(&errors[2], AsimovError::Model(m) if m == "unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_router() -> Result<()> {
+        let llm = Router::by_tokens(Echo::named("short"), Echo::named("long"), 5);
+
+        let answer: RawString = llm.generate("Hi").await?;
+        assert_eq!(answer.0, "short: Hi");
+
+        let answer: RawString = llm
+            .generate("This prompt is a little longer than five tokens")
+            .await?;
+        assert!(answer.0.starts_with("long: "));
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Models shared by the tests.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use async_trait::async_trait;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::{Candidates, Input, RawString, TokenStream},
+    models::{CacheConfig, Embed, Generate, GenerationOptions},
+    tokenizers::openai::OpenAiTiktoken,
+};
+
+/// Answers with the prompt, prefixed with its name if it has one, and
+/// counts its calls. Streams the answer word by word, and answers
+/// candidates with the `n` first words of the prompt.
+#[derive(Debug, Default)]
+pub(crate) struct Echo {
+    name: Option<&'static str>,
+    calls: AtomicU32,
+}
+
+impl Echo {
+    pub(crate) fn named(name: &'static str) -> Self {
+        Self {
+            name: Some(name),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn calls(&self) -> u32 {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    fn answer(&self, input: impl Input) -> Result<String> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let prompt = input.render()?;
+        Ok(match self.name {
+            Some(name) => format!("{name}: {prompt}"),
+            None => prompt,
+        })
+    }
+}
+
+#[async_trait]
+impl Generate<RawString> for Echo {
+    async fn generate_with(&self, input: impl Input, _: &GenerationOptions) -> Result<RawString> {
+        Ok(RawString::new(self.answer(input)?))
+    }
+}
+
+#[async_trait]
+impl Generate<TokenStream> for Echo {
+    async fn generate_with(&self, input: impl Input, _: &GenerationOptions) -> Result<TokenStream> {
+        let words: Vec<Result<String>> = self
+            .answer(input)?
+            .split_inclusive(' ')
+            .map(|w| Ok(w.to_string()))
+            .collect();
+        Ok(TokenStream::new(futures::stream::iter(words)))
+    }
+}
+
+#[async_trait]
+impl Generate<Candidates<RawString>> for Echo {
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Candidates<RawString>> {
+        let answer = self.answer(input)?;
+        let words = answer.split(' ').take(options.n.unwrap_or(1) as usize);
+        Ok(Candidates(
+            words.map(|w| RawString::new(w.to_string())).collect(),
+        ))
+    }
+}
+
+impl CacheConfig for Echo {
+    fn cache_config(&self) -> serde_json::Value {
+        serde_json::json!({ "model": "echo", "name": self.name })
+    }
+}
+
+/// Fails with [`AsimovError::Model`].
+pub(crate) struct Failing;
+
+#[async_trait]
+impl Generate<RawString> for Failing {
+    async fn generate_with(&self, _: impl Input, _: &GenerationOptions) -> Result<RawString> {
+        Err(AsimovError::Model("unavailable".to_string()))
+    }
+}
+
+#[async_trait]
+impl Generate<TokenStream> for Failing {
+    async fn generate_with(&self, _: impl Input, _: &GenerationOptions) -> Result<TokenStream> {
+        Err(AsimovError::Model("unavailable".to_string()))
+    }
+}
+
+/// Embeds texts by their letter frequencies, and counts its calls.
+#[derive(Debug, Default)]
+pub(crate) struct Letters {
+    calls: AtomicU32,
+}
+
+impl Letters {
+    pub(crate) fn calls(&self) -> u32 {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Embed for Letters {
+    type Tokenizer = OpenAiTiktoken;
+
+    fn dim(&self) -> usize {
+        26
+    }
+
+    async fn embed<I: Input + ?Sized>(&self, input: &I) -> Result<Vec<f32>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let mut embedding = vec![0.0; 26];
+        for c in input.render()?.to_lowercase().chars() {
+            if c.is_ascii_lowercase() {
+                embedding[(c as u8 - b'a') as usize] += 1.0;
+            }
+        }
+        Ok(embedding)
+    }
+}
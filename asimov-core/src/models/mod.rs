@@ -1,8 +1,25 @@
+pub mod cache;
+#[cfg(feature = "candle")]
+pub mod candle;
 pub mod capabilities;
+pub mod fallback;
+#[cfg(test)]
+pub(crate) mod mocks;
 #[cfg(feature = "openai")]
 pub mod openai;
+pub mod options;
+pub mod rag;
+pub mod rate_limit;
+pub mod rerank;
+pub mod retry;
+pub mod self_consistency;
 
-#[cfg(feature = "openai")]
-use self::openai::{OpenAiEmbedding, OpenAiLlm};
-
-pub use capabilities::{Embed, Generate};
+pub use cache::{CacheConfig, CacheStore, Cacheable, Cached, DiskStore, LruStore};
+pub use capabilities::{Embed, Generate, GenerateN, Rerank};
+pub use fallback::{Fallback, Router};
+pub use options::GenerationOptions;
+pub use rag::{Rag, RagAnswer};
+pub use rate_limit::{RateLimited, RateLimiter, RateLimits};
+pub use rerank::LlmReranker;
+pub use retry::{Retry, RetryPolicy};
+pub use self_consistency::{Majority, Select, SelfConsistency};
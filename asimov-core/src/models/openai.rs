@@ -1,42 +1,108 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_openai::{
+    config::{Config, OpenAIConfig},
+    error::{ApiError, OpenAIError},
     types::{
-        ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
-        CreateEmbeddingRequestArgs,
+        ChatCompletionRequestMessageContentPart, ChatCompletionRequestMessageContentPartImageArgs,
+        ChatCompletionRequestMessageContentPartTextArgs, ChatCompletionRequestUserMessageArgs,
+        ChatCompletionRequestUserMessageContent, ChatCompletionTokenLogprob, CompletionUsage,
+        CreateChatCompletionRequest, CreateChatCompletionRequestArgs, CreateChatCompletionResponse,
+        CreateChatCompletionStreamResponse, CreateEmbeddingRequestArgs,
+        FinishReason as OpenAiFinishReason, ImageUrlArgs, ImageUrlDetail, Stop,
     },
     Client,
 };
 use async_stream::stream;
 use async_trait::async_trait;
-use futures::StreamExt;
+use backoff::ExponentialBackoffBuilder;
+use futures::{Stream, StreamExt};
 use serde::{de::DeserializeOwned, Deserialize};
 use typed_builder::TypedBuilder;
 
 use crate::{
     error::Result,
-    io::{Input, RawString, StreamedOutput},
+    io::{
+        AsimovOutput, Candidates, ContentPart, FinishReason, Generation, GenerationMetadata,
+        ImageDetail, Input, JsonStreamOptions, MetadataSlot, Partial, RawString, SseParser,
+        StreamedOutput, Structured, TokenLogprob, TopLogprob, Usage, WithFormatInstructions,
+        WithLogprobs,
+    },
     tokenizers::openai::OpenAiTiktoken,
     AsimovError,
 };
 
-use super::capabilities::{Embed, Generate};
+use super::{
+    cache::CacheConfig,
+    capabilities::{Embed, Generate},
+    options::GenerationOptions,
+    retry::RetryPolicy,
+};
 use crate::io::{JsonStream, TokenStream};
 
-#[derive(TypedBuilder, Clone)]
+#[derive(TypedBuilder, Debug, Clone)]
 pub struct OpenAiLlm {
     #[builder(default = "gpt-3.5-turbo".to_string())]
     /// Model name. See the [OpenAI docs](https://platform.openai.com/docs/guides/text-generation)
     model: String,
     #[builder(default, setter(strip_option))]
     /// Stop after `max_tokens` tokens
-    max_tokens: Option<u16>,
+    max_tokens: Option<u32>,
+    #[builder(default, setter(into))]
+    /// Stopping criterion: stop generation upon any of these sequences
+    stop: Vec<String>,
     #[builder(default, setter(strip_option))]
-    /// Stopping criterion: stop generation upon detected sequence
-    stop: Option<String>,
-    #[builder(default, setter(strip_option))]
-    /// Stopping criterion: stop generation upon detected sequence
+    /// Sampling temperature, from 0.0 to 2.0
     temperature: Option<f32>,
+    #[builder(default, setter(strip_option))]
+    /// Nucleus sampling: only sample from the tokens within the top `top_p`
+    /// probability mass
+    top_p: Option<f32>,
+    #[builder(default, setter(strip_option))]
+    /// Penalize the tokens already present in the output, from -2.0 to 2.0
+    presence_penalty: Option<f32>,
+    #[builder(default, setter(strip_option))]
+    /// Penalize the tokens in proportion to their frequency in the output,
+    /// from -2.0 to 2.0
+    frequency_penalty: Option<f32>,
+    #[builder(default, setter(strip_option))]
+    /// Bias added to the logits of the given token ids, from -100 to 100
+    logit_bias: Option<BTreeMap<u32, i32>>,
+    #[builder(default, setter(strip_option))]
+    /// Seed for a (mostly) deterministic sampling
+    seed: Option<i64>,
+    #[builder(default, setter(strip_option))]
+    /// Number of answers generated for [`Candidates`] outputs
+    n: Option<u8>,
+    #[builder(default, setter(strip_option))]
+    /// Number of alternatives returned for each token of [`WithLogprobs`]
+    /// outputs, up to 20
+    top_logprobs: Option<u8>,
+    #[builder(default, setter(strip_option, into))]
+    /// Identifier of the end user, for abuse monitoring
+    user: Option<String>,
+    #[builder(default)]
+    /// Cleanup of the output before parsing it into a `StreamedOutput`
+    json_stream: JsonStreamOptions,
+    #[builder(default, setter(strip_option))]
+    /// Maximum duration of a generation, including the whole stream
+    timeout: Option<Duration>,
+    #[builder(default, setter(strip_option))]
+    /// Maximum delay between two streamed tokens
+    idle_timeout: Option<Duration>,
+    #[builder(default, setter(strip_option, into))]
+    /// Base URL of the API, e.g. for a proxy or an OpenAI-compatible server
+    api_base: Option<String>,
+    #[builder(default)]
+    /// Retries of requests failing with a transient error
+    retry: RetryPolicy,
+    #[builder(default)]
+    /// HTTP client sending the requests, whose connections are reused
+    http_client: reqwest::Client,
 }
 
 impl Default for OpenAiLlm {
@@ -46,82 +112,567 @@ impl Default for OpenAiLlm {
             max_tokens: Default::default(),
             stop: Default::default(),
             temperature: Default::default(),
+            top_p: Default::default(),
+            presence_penalty: Default::default(),
+            frequency_penalty: Default::default(),
+            logit_bias: Default::default(),
+            seed: Default::default(),
+            n: Default::default(),
+            top_logprobs: Default::default(),
+            user: Default::default(),
+            json_stream: Default::default(),
+            timeout: Default::default(),
+            idle_timeout: Default::default(),
+            api_base: Default::default(),
+            retry: Default::default(),
+            http_client: Default::default(),
         }
     }
 }
 
 impl OpenAiLlm {
-    /// Generate a request to the LLM
-    fn request(&self, input: impl Input) -> Result<CreateChatCompletionRequestArgs> {
-        let prompt = input.render()?;
+    /// Settings of the model, as generation options.
+    pub fn options(&self) -> GenerationOptions {
+        GenerationOptions {
+            model: Some(self.model.clone()),
+            max_tokens: self.max_tokens,
+            stop: (!self.stop.is_empty()).then(|| self.stop.clone()),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            logit_bias: self.logit_bias.clone(),
+            seed: self.seed,
+            n: self.n,
+            top_logprobs: self.top_logprobs,
+            user: self.user.clone(),
+        }
+    }
+
+    /// Generate a request to the LLM for `n` answers, with `overrides`
+    /// taking precedence over the settings of the model.
+    fn request(
+        &self,
+        input: impl Input,
+        overrides: &GenerationOptions,
+        n: u8,
+    ) -> Result<CreateChatCompletionRequestArgs> {
+        let options = self.options().merge(overrides);
 
         let message = ChatCompletionRequestUserMessageArgs::default()
-            .content(prompt)
+            .content(user_content(input)?)
             .build()?
             .into();
 
         let mut request = CreateChatCompletionRequestArgs::default();
 
         request
-            .model(&self.model.to_string())
-            .n(1)
+            .model(options.model.unwrap_or_else(|| self.model.clone()))
+            .n(n)
             .messages([message]);
 
-        if let Some(stop) = &self.stop {
-            request.stop(stop);
-        }
-        if let Some(max_tokens) = self.max_tokens {
-            request.max_tokens(max_tokens);
+        if let Some(stop) = options.stop {
+            request.stop(Stop::StringArray(stop));
         }
-        if let Some(temperature) = self.temperature {
+        if let Some(temperature) = options.temperature {
             request.temperature(temperature);
         }
+        if let Some(top_p) = options.top_p {
+            request.top_p(top_p);
+        }
+        if let Some(presence_penalty) = options.presence_penalty {
+            request.presence_penalty(presence_penalty);
+        }
+        if let Some(frequency_penalty) = options.frequency_penalty {
+            request.frequency_penalty(frequency_penalty);
+        }
+        if let Some(logit_bias) = options.logit_bias {
+            let logit_bias = logit_bias
+                .into_iter()
+                .map(|(token, bias)| (token.to_string(), bias.into()))
+                .collect::<HashMap<_, _>>();
+            request.logit_bias(logit_bias);
+        }
+        if let Some(seed) = options.seed {
+            request.seed(seed);
+        }
+        if let Some(user) = options.user {
+            request.user(user);
+        }
 
         Ok(request)
     }
 
+    /// Body of a chat completion `request`, with the `max_tokens` of the
+    /// model or of `overrides`, which async-openai 0.19 caps at `u16::MAX`.
+    fn body(
+        &self,
+        request: &CreateChatCompletionRequest,
+        overrides: &GenerationOptions,
+    ) -> Result<serde_json::Value> {
+        let mut body = serde_json::to_value(request)?;
+        if let Some(max_tokens) = self.options().merge(overrides).max_tokens {
+            body["max_tokens"] = max_tokens.into();
+        }
+        Ok(body)
+    }
+
     /// Use the model to generate a `String` response.
-    async fn raw_string(&self, input: impl Input) -> Result<String> {
-        let client = Client::new();
+    async fn raw_string(&self, input: impl Input, options: &GenerationOptions) -> Result<String> {
+        Ok(self.raw_generation(input, options).await?.into_inner())
+    }
 
-        let request = self.request(input)?.build()?;
+    /// Use the model to generate a `String` response, along with the
+    /// metadata reported by the API.
+    async fn raw_generation(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Generation<String>> {
+        let request = self.request(input, options, 1)?.build()?;
+        let (response, latency) = self.completion(self.body(&request, options)?).await?;
+
+        let choice =
+            response.choices.into_iter().nth(0).ok_or_else(|| {
+                AsimovError::Output("No choices returned from OpenAI".to_string())
+            })?;
+        let result = content(choice.message.content)?;
+
+        let metadata = GenerationMetadata {
+            model: response.model,
+            id: Some(response.id),
+            finish_reason: choice.finish_reason.map(FinishReason::from),
+            usage: response.usage.map(Usage::from),
+            latency,
+        };
 
-        let response = client.chat().create(request).await?;
+        Ok(Generation::new(result, metadata))
+    }
 
-        let result = response
+    /// Use the model to generate `n` `String` responses, `n` being set by
+    /// the options or the model and defaulting to 1.
+    async fn raw_candidates(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Vec<String>> {
+        let n = options.n.or(self.n).unwrap_or(1);
+        let request = self.request(input, options, n)?.build()?;
+        let (response, _) = self.completion(self.body(&request, options)?).await?;
+
+        response
             .choices
             .into_iter()
-            .nth(0)
-            .ok_or_else(|| AsimovError::Output("No choices returned from OpenAI".to_string()))?
-            .message
-            .content
-            .ok_or_else(|| {
-                AsimovError::Output("No content in the choice returned from OpenAI".to_string())
+            .map(|choice| content(choice.message.content))
+            .collect()
+    }
+
+    /// Use the model to generate a `String` response, along with the log
+    /// probabilities of its tokens.
+    async fn raw_logprobs(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<WithLogprobs<String>> {
+        let top_logprobs = options.top_logprobs.or(self.top_logprobs).unwrap_or(5);
+        let request = self
+            .request(input, options, 1)?
+            .logprobs(true)
+            .top_logprobs(top_logprobs)
+            .build()?;
+        let (response, _) = self.completion(self.body(&request, options)?).await?;
+
+        let choice =
+            response.choices.into_iter().nth(0).ok_or_else(|| {
+                AsimovError::Output("No choices returned from OpenAI".to_string())
             })?;
+        let tokens = choice
+            .logprobs
+            .and_then(|logprobs| logprobs.content)
+            .ok_or_else(|| AsimovError::Output("No logprobs returned from OpenAI".to_string()))?
+            .into_iter()
+            .map(TokenLogprob::from)
+            .collect();
+
+        Ok(WithLogprobs::new(content(choice.message.content)?, tokens))
+    }
+
+    /// Send a chat completion request, returning the response and its latency.
+    async fn completion(
+        &self,
+        body: serde_json::Value,
+    ) -> Result<(CreateChatCompletionResponse, Duration)> {
+        let client = client(&self.api_base, &self.http_client);
+
+        let start = Instant::now();
+        let response = self
+            .retry
+            .retry(|| async {
+                self.within_timeout(chat_completion(&self.http_client, &client, &body))
+                    .await
+            })
+            .await?;
 
-        Ok(result)
+        Ok((response, start.elapsed()))
     }
 
     /// Create a stream over the tokens generated by the LLM.
     /// This is the building block for streaming responses.
-    async fn stream_tokens(&self, input: impl Input) -> Result<TokenStream> {
-        let client = Client::new();
-        let request = self.request(input)?.build()?;
+    async fn stream_tokens(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        let client = client(&self.api_base, &self.http_client);
+        let request = self.request(input, options, 1)?.build()?;
+        let body = self.body(&request, options)?;
+
+        let start = Instant::now();
+        let stream = self
+            .retry
+            .retry(|| async {
+                self.within_timeout(chat_stream(&self.http_client, &client, body.clone()))
+                    .await
+            })
+            .await?;
 
-        let mut stream = client.chat().create_stream(request).await?;
+        let mut tokens = token_stream(stream, start);
+        if let Some(timeout) = self.timeout {
+            tokens = tokens.timeout_at((start + timeout).into(), timeout);
+        }
+        if let Some(idle_timeout) = self.idle_timeout {
+            tokens = tokens.idle_timeout(idle_timeout);
+        }
+        Ok(tokens)
+    }
 
-        let s = stream! {
-            while let Some(chunk) = stream.next().await {
-                let choice = chunk.unwrap().choices.into_iter().nth(0).unwrap();
-                let content = choice.delta.content;
+    /// Run a request to the API, failing with [`AsimovError::Timeout`] after `timeout`.
+    async fn within_timeout<T, E: Into<AsimovError>>(
+        &self,
+        request: impl std::future::Future<Output = std::result::Result<T, E>>,
+    ) -> Result<T> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, request)
+                .await
+                .map_err(|_| AsimovError::Timeout(timeout))?
+                .map_err(Into::into),
+            None => request.await.map_err(Into::into),
+        }
+    }
+}
 
-                if let Some(text) = content {
-                    yield text
+/// Content of the user message: the rendered input, or its text and
+/// images if it has any.
+fn user_content(input: impl Input) -> Result<ChatCompletionRequestUserMessageContent> {
+    let content = input.content()?;
+    if !content.iter().any(ContentPart::is_image) {
+        return Ok(ChatCompletionRequestUserMessageContent::Text(
+            input.render()?,
+        ));
+    }
+
+    let parts = content
+        .into_iter()
+        .map(|part| {
+            Ok(match part {
+                ContentPart::Text(text) => {
+                    ChatCompletionRequestMessageContentPartTextArgs::default()
+                        .text(text)
+                        .build()?
+                        .into()
+                }
+                ContentPart::Image(image) => {
+                    let detail = match image.detail {
+                        ImageDetail::Auto => ImageUrlDetail::Auto,
+                        ImageDetail::Low => ImageUrlDetail::Low,
+                        ImageDetail::High => ImageUrlDetail::High,
+                    };
+                    let image_url = ImageUrlArgs::default()
+                        .url(image.url())
+                        .detail(detail)
+                        .build()?;
+                    ChatCompletionRequestMessageContentPartImageArgs::default()
+                        .image_url(image_url)
+                        .build()?
+                        .into()
                 }
+            })
+        })
+        .collect::<Result<Vec<ChatCompletionRequestMessageContentPart>>>()?;
+
+    Ok(ChatCompletionRequestUserMessageContent::Array(parts))
+}
+
+/// Content of a choice returned by the API.
+fn content(content: Option<String>) -> Result<String> {
+    content.ok_or_else(|| {
+        AsimovError::Output("No content in the choice returned from OpenAI".to_string())
+    })
+}
+
+/// Client of the API at `api_base`, or at the default OpenAI URL, sending
+/// its requests with `http_client`.
+fn client(api_base: &Option<String>, http_client: &reqwest::Client) -> Client<OpenAIConfig> {
+    let mut config = OpenAIConfig::new();
+    if let Some(api_base) = api_base {
+        config = config.with_api_base(api_base);
+    }
+
+    // async-openai retries rate limited requests for up to 15 minutes on its
+    // own: leave retries to our `RetryPolicy` instead.
+    let backoff = ExponentialBackoffBuilder::new()
+        .with_max_elapsed_time(Some(Duration::ZERO))
+        .build();
+
+    Client::with_config(config)
+        .with_http_client(http_client.clone())
+        .with_backoff(backoff)
+}
+
+/// Chunk of a streamed chat completion.
+///
+/// async-openai 0.19 predates `stream_options`: the usage sent in the last
+/// chunk, which has no choices, is read alongside the chunk.
+#[derive(Deserialize)]
+struct ChatChunk {
+    #[serde(flatten)]
+    response: CreateChatCompletionStreamResponse,
+    usage: Option<CompletionUsage>,
+}
+
+type ChunkStream =
+    std::pin::Pin<Box<dyn Stream<Item = std::result::Result<ChatChunk, OpenAIError>> + Send>>;
+
+/// Body of an error response of the API.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ApiError,
+}
+
+/// Send a chat completion request with `http_client`, as `body` holds
+/// options async-openai cannot send. Error responses are returned as
+/// [`AsimovError::OpenAIStatus`].
+async fn send_chat(
+    http_client: &reqwest::Client,
+    client: &Client<OpenAIConfig>,
+    body: &serde_json::Value,
+) -> Result<reqwest::Response> {
+    let body = serde_json::to_vec(body)?;
+    let response = http_client
+        .post(client.config().url("/chat/completions"))
+        .headers(client.config().headers())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        .map_err(OpenAIError::Reqwest)?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = retry_after(response.headers());
+    let body = response.bytes().await.map_err(OpenAIError::Reqwest)?;
+    // Proxies and load balancers may answer with a page that is not JSON:
+    // its text becomes the message.
+    let error = match serde_json::from_slice::<ErrorResponse>(&body) {
+        Ok(response) => response.error,
+        Err(_) => ApiError {
+            message: String::from_utf8_lossy(&body).into_owned(),
+            r#type: None,
+            param: None,
+            code: None,
+        },
+    };
+    Err(AsimovError::OpenAIStatus {
+        status: status.as_u16(),
+        error: Box::new(error),
+        retry_after,
+    })
+}
+
+/// Send a chat completion request.
+async fn chat_completion(
+    http_client: &reqwest::Client,
+    client: &Client<OpenAIConfig>,
+    body: &serde_json::Value,
+) -> Result<CreateChatCompletionResponse> {
+    let response = send_chat(http_client, client, body).await?;
+    let body = response.bytes().await.map_err(OpenAIError::Reqwest)?;
+    Ok(serde_json::from_slice(&body).map_err(OpenAIError::JSONDeserialize)?)
+}
+
+/// Send a streamed chat completion request.
+///
+/// `Chat::create_stream` reads the response from a task of its own, which
+/// keeps the connection open until the server sends another event, even once
+/// the stream is dropped. Here the stream owns the response body instead:
+/// dropping it, e.g. on cancellation or timeout, closes the connection.
+async fn chat_stream(
+    http_client: &reqwest::Client,
+    client: &Client<OpenAIConfig>,
+    mut body: serde_json::Value,
+) -> Result<ChunkStream> {
+    body["stream"] = true.into();
+    body["stream_options"] = serde_json::json!({ "include_usage": true });
+    let response = send_chat(http_client, client, &body).await?;
+
+    Ok(chat_events(response.bytes_stream()))
+}
+
+/// Delay requested by the `retry-after-ms` or `Retry-After` headers of a
+/// response. `Retry-After` dates are not supported, only delays in seconds.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+    let seconds = header("retry-after-ms")
+        .map(|ms| ms / 1000.0)
+        .or_else(|| header("retry-after"))?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Parse the server-sent events of a chat completion, up to `[DONE]`.
+///
+/// An `error` event, or an event holding an error object, ends the stream
+/// with that error.
+fn chat_events<B, S>(bytes: S) -> ChunkStream
+where
+    B: AsRef<[u8]> + Send,
+    S: Stream<Item = reqwest::Result<B>> + Send + 'static,
+{
+    Box::pin(stream! {
+        let mut bytes = Box::pin(bytes);
+        let mut parser = SseParser::new();
+
+        while let Some(received) = bytes.next().await {
+            let received = match received {
+                Ok(received) => received,
+                Err(e) => {
+                    yield Err(OpenAIError::StreamError(e.to_string()));
+                    return;
+                }
+            };
+
+            for event in parser.push(received.as_ref()) {
+                if event.data == "[DONE]" {
+                    return;
+                }
+                let error = if event.event.as_deref() == Some("error") {
+                    OpenAIError::StreamError(event.data.clone())
+                } else {
+                    match serde_json::from_str::<ChatChunk>(&event.data) {
+                        Ok(chunk) => {
+                            yield Ok(chunk);
+                            continue;
+                        }
+                        Err(e) => OpenAIError::JSONDeserialize(e),
+                    }
+                };
+                // Errors sent mid-stream are error objects, as in error responses.
+                yield Err(match serde_json::from_str::<ErrorResponse>(&event.data) {
+                    Ok(response) => OpenAIError::ApiError(response.error),
+                    Err(_) => error,
+                });
+                return;
             }
-        };
+        }
+    })
+}
 
-        Ok(TokenStream::new(s))
+/// Turn the chunks streamed by the chat completion API into a [`TokenStream`].
+///
+/// Errors are forwarded to the consumer and end the stream. Chunks without
+/// choices or content are skipped. The metadata is filled in once the
+/// last chunk is received, along with the usage it reports.
+fn token_stream<S>(mut chunks: S, start: Instant) -> TokenStream
+where
+    S: Stream<Item = std::result::Result<ChatChunk, OpenAIError>> + Send + Unpin + 'static,
+{
+    let slot = MetadataSlot::new();
+    let metadata_slot = slot.clone();
+
+    let s = stream! {
+        let mut metadata = GenerationMetadata::default();
+
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Ok(ChatChunk { response: chunk, usage }) => {
+                    metadata.model = chunk.model;
+                    metadata.id = Some(chunk.id);
+                    if let Some(usage) = usage {
+                        metadata.usage = Some(usage.into());
+                    }
+
+                    let Some(choice) = chunk.choices.into_iter().next() else {
+                        continue;
+                    };
+                    if let Some(reason) = choice.finish_reason {
+                        metadata.finish_reason = Some(reason.into());
+                    }
+                    if let Some(text) = choice.delta.content {
+                        yield Ok(text)
+                    }
+                }
+                Err(e) => {
+                    yield Err(AsimovError::from(e));
+                    return;
+                }
+            }
+        }
+
+        metadata.latency = start.elapsed();
+        metadata_slot.set(metadata);
+    };
+
+    TokenStream::with_metadata(s, slot)
+}
+
+impl From<OpenAiFinishReason> for FinishReason {
+    fn from(reason: OpenAiFinishReason) -> Self {
+        match reason {
+            OpenAiFinishReason::Stop => FinishReason::Stop,
+            OpenAiFinishReason::Length => FinishReason::Length,
+            OpenAiFinishReason::ContentFilter => FinishReason::ContentFilter,
+            OpenAiFinishReason::ToolCalls => FinishReason::ToolCalls,
+            OpenAiFinishReason::FunctionCall => FinishReason::FunctionCall,
+        }
+    }
+}
+
+impl From<ChatCompletionTokenLogprob> for TokenLogprob {
+    fn from(logprob: ChatCompletionTokenLogprob) -> Self {
+        TokenLogprob {
+            token: logprob.token,
+            logprob: logprob.logprob,
+            top_logprobs: logprob
+                .top_logprobs
+                .into_iter()
+                .map(|top| TopLogprob {
+                    token: top.token,
+                    logprob: top.logprob,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<CompletionUsage> for Usage {
+    fn from(usage: CompletionUsage) -> Self {
+        Usage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+impl CacheConfig for OpenAiLlm {
+    fn cache_config(&self) -> serde_json::Value {
+        serde_json::json!({
+            "provider": "openai",
+            "api_base": self.api_base,
+            "options": self.options(),
+        })
     }
 }
 
@@ -138,18 +689,91 @@ where
     ///
     /// To generate "raw" strings, use the [`RawString`] type
     /// instead.
-    async fn generate(&self, input: impl Input) -> Result<S> {
-        let raw = self.raw_string(input).await?;
+    async fn generate_with(&self, input: impl Input, options: &GenerationOptions) -> Result<S> {
+        let raw = self.raw_string(input, options).await?;
         let parsed = serde_json::from_str(&raw)?;
         Ok(parsed)
     }
 }
 
+#[async_trait]
+impl<T> Generate<Structured<T>> for OpenAiLlm
+where
+    T: AsimovOutput + DeserializeOwned + Send + 'static,
+{
+    /// Append the format instructions of `T` to the prompt, then parse
+    /// the response as JSON.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Structured<T>> {
+        let raw = self
+            .raw_string(WithFormatInstructions::<_, T>::new(input), options)
+            .await?;
+        let parsed = serde_json::from_str(&raw)?;
+        Ok(Structured(parsed))
+    }
+}
+
+#[async_trait]
+impl<S> Generate<Generation<S>> for OpenAiLlm
+where
+    for<'a> S: Deserialize<'a>,
+{
+    /// Parse the response like `Generate<S>`, keeping the generation metadata.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Generation<S>> {
+        let generation = self.raw_generation(input, options).await?;
+        generation.try_map(|raw| Ok(serde_json::from_str(&raw)?))
+    }
+}
+
+#[async_trait]
+impl<T> Generate<Generation<Structured<T>>> for OpenAiLlm
+where
+    T: AsimovOutput + DeserializeOwned + Send + 'static,
+{
+    /// Like `Generate<Structured<T>>`, keeping the generation metadata.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Generation<Structured<T>>> {
+        let generation = self
+            .raw_generation(WithFormatInstructions::<_, T>::new(input), options)
+            .await?;
+        generation.try_map(|raw| Ok(Structured(serde_json::from_str(&raw)?)))
+    }
+}
+
+#[async_trait]
+impl Generate<Generation<RawString>> for OpenAiLlm {
+    /// Pass the output of the LLM directly, along with the generation metadata.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Generation<RawString>> {
+        Ok(self
+            .raw_generation(input, options)
+            .await?
+            .map(RawString::new))
+    }
+}
+
 #[async_trait]
 impl Generate<RawString> for OpenAiLlm {
     /// Pass the output of the LLM directly.
-    async fn generate(&self, input: impl Input) -> Result<RawString> {
-        let raw = self.raw_string(input).await?;
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<RawString> {
+        let raw = self.raw_string(input, options).await?;
         Ok(RawString::new(raw))
     }
 }
@@ -157,21 +781,56 @@ impl Generate<RawString> for OpenAiLlm {
 #[async_trait]
 impl Generate<TokenStream> for OpenAiLlm {
     /// Stream the tokens generated by the LLM directly.
-    async fn generate(&self, input: impl Input) -> Result<TokenStream> {
-        self.stream_tokens(input).await
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        self.stream_tokens(input, options).await
     }
 }
 
 #[async_trait]
 impl<D: DeserializeOwned + Send + 'static> Generate<StreamedOutput<D>> for OpenAiLlm {
     /// Use `json_stream` to stream any type that implements
-    /// [`Deserialize`].
-    async fn generate(&self, input: impl Input) -> Result<StreamedOutput<D>> {
-        let stream = self.stream_tokens(input).await?;
-        let stream = stream.map(|t| Ok(t.into_bytes()));
+    /// [`Deserialize`]. The output is first cleaned up according to the
+    /// `json_stream` options of the model.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<StreamedOutput<D>> {
+        let stream = self
+            .stream_tokens(input, options)
+            .await?
+            .sanitize_json(self.json_stream);
+        let metadata = stream.metadata_slot();
+        let cancel = stream.cancel_handle();
+        let stream = stream.map(|t| t.map(String::into_bytes));
         let stream = JsonStream::<D>::new(Box::pin(stream));
 
-        Ok(StreamedOutput::<D>::new(stream))
+        Ok(StreamedOutput::<D>::with_metadata(stream, metadata).with_cancel_handle(cancel))
+    }
+}
+
+#[async_trait]
+impl<D: DeserializeOwned + Send + 'static> Generate<StreamedOutput<Partial<D>>> for OpenAiLlm {
+    /// Stream snapshots of a single JSON value as it is generated.
+    /// See [`Partial`].
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<StreamedOutput<Partial<D>>> {
+        let json_stream = JsonStreamOptions {
+            unwrap_array: false,
+            ..self.json_stream
+        };
+        let stream = self
+            .stream_tokens(input, options)
+            .await?
+            .sanitize_json(json_stream);
+        Ok(StreamedOutput::from_tokens(stream))
     }
 }
 
@@ -186,45 +845,205 @@ impl Generate<StreamedOutput<RawString>> for OpenAiLlm {
     ///
     /// Consider using [`TokenStream`] as the result type, since it will
     /// stream tokens, as [`String`], directly.
-    async fn generate(&self, input: impl Input) -> Result<StreamedOutput<RawString>> {
-        let stream = self.stream_tokens(input).await?;
-        let stream = StreamedOutput::<RawString>::new(stream.map(|t| Ok(RawString::new(t))));
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<StreamedOutput<RawString>> {
+        let stream = self.stream_tokens(input, options).await?;
+        let metadata = stream.metadata_slot();
+        let cancel = stream.cancel_handle();
+        let stream = StreamedOutput::<RawString>::with_metadata(
+            stream.map(|t| t.map(RawString::new)),
+            metadata,
+        )
+        .with_cancel_handle(cancel);
         Ok(stream)
     }
 }
 
+#[async_trait]
+impl<S> Generate<Candidates<S>> for OpenAiLlm
+where
+    for<'a> S: Deserialize<'a> + Send,
+{
+    /// Parse each answer like `Generate<S>`.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Candidates<S>> {
+        let raw = self.raw_candidates(input, options).await?;
+        let parsed = raw
+            .iter()
+            .map(|raw| serde_json::from_str(raw))
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(Candidates(parsed))
+    }
+}
+
+#[async_trait]
+impl<T> Generate<Candidates<Structured<T>>> for OpenAiLlm
+where
+    T: AsimovOutput + DeserializeOwned + Send + 'static,
+{
+    /// Parse each answer like `Generate<Structured<T>>`.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Candidates<Structured<T>>> {
+        let raw = self
+            .raw_candidates(WithFormatInstructions::<_, T>::new(input), options)
+            .await?;
+        let parsed = raw
+            .iter()
+            .map(|raw| Ok(Structured(serde_json::from_str(raw)?)))
+            .collect::<Result<_>>()?;
+        Ok(Candidates(parsed))
+    }
+}
+
+#[async_trait]
+impl Generate<Candidates<RawString>> for OpenAiLlm {
+    /// Pass each answer of the LLM directly.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<Candidates<RawString>> {
+        let raw = self.raw_candidates(input, options).await?;
+        Ok(Candidates(raw.into_iter().map(RawString::new).collect()))
+    }
+}
+
+#[async_trait]
+impl<S> Generate<WithLogprobs<S>> for OpenAiLlm
+where
+    for<'a> S: Deserialize<'a> + Send,
+{
+    /// Parse the response like `Generate<S>`, keeping the log probabilities
+    /// of its tokens.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<WithLogprobs<S>> {
+        let raw = self.raw_logprobs(input, options).await?;
+        let value = serde_json::from_str(&raw.value)?;
+        Ok(WithLogprobs::new(value, raw.tokens))
+    }
+}
+
+#[async_trait]
+impl<T> Generate<WithLogprobs<Structured<T>>> for OpenAiLlm
+where
+    T: AsimovOutput + DeserializeOwned + Send + 'static,
+{
+    /// Like `Generate<Structured<T>>`, keeping the log probabilities of the
+    /// tokens.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<WithLogprobs<Structured<T>>> {
+        let raw = self
+            .raw_logprobs(WithFormatInstructions::<_, T>::new(input), options)
+            .await?;
+        let value = Structured(serde_json::from_str(&raw.value)?);
+        Ok(WithLogprobs::new(value, raw.tokens))
+    }
+}
+
+#[async_trait]
+impl Generate<WithLogprobs<RawString>> for OpenAiLlm {
+    /// Pass the output of the LLM directly, along with the log
+    /// probabilities of its tokens.
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<WithLogprobs<RawString>> {
+        let raw = self.raw_logprobs(input, options).await?;
+        Ok(WithLogprobs::new(RawString::new(raw.value), raw.tokens))
+    }
+}
+
 /// Struct handling the interaction with OpenAI's API for embedding text.
-#[derive(TypedBuilder, Clone)]
+#[derive(TypedBuilder, Debug, Clone)]
 pub struct OpenAiEmbedding {
     #[builder(default = "text-embedding-ada-002".to_string())]
     model: String,
+    #[builder(default, setter(strip_option))]
+    /// Length of the embeddings, for models that can shorten them
+    /// (`text-embedding-3-*`). Defaults to the native length of the model.
+    dimensions: Option<u32>,
+    #[builder(default, setter(strip_option, into))]
+    /// Base URL of the API, e.g. for a proxy or an OpenAI-compatible server
+    api_base: Option<String>,
+    #[builder(default)]
+    /// Retries of requests failing with a transient error
+    retry: RetryPolicy,
+    #[builder(default)]
+    /// HTTP client sending the requests, whose connections are reused
+    http_client: reqwest::Client,
 }
 
 impl Default for OpenAiEmbedding {
     fn default() -> Self {
         Self {
             model: "text-embedding-ada-002".to_string(),
+            dimensions: Default::default(),
+            api_base: Default::default(),
+            retry: Default::default(),
+            http_client: Default::default(),
         }
     }
 }
 
+impl CacheConfig for OpenAiEmbedding {
+    fn cache_config(&self) -> serde_json::Value {
+        serde_json::json!({
+            "provider": "openai",
+            "api_base": self.api_base,
+            "model": self.model,
+            "dimensions": self.dimensions,
+        })
+    }
+}
+
 #[async_trait]
 impl Embed for OpenAiEmbedding {
     type Tokenizer = OpenAiTiktoken;
-    const DIM: u32 = 1536;
+
+    fn dim(&self) -> usize {
+        if let Some(dimensions) = self.dimensions {
+            return dimensions as usize;
+        }
+        match self.model.as_str() {
+            "text-embedding-3-large" => 3072,
+            // text-embedding-ada-002 and text-embedding-3-small
+            _ => 1536,
+        }
+    }
 
     /// Embed any type that implement the [`Input`] trait.
     async fn embed<I: Input + ?Sized>(&self, input: &I) -> Result<Vec<f32>> {
         let prompt = input.render()?;
 
-        let client = Client::new();
+        let client = client(&self.api_base, &self.http_client);
 
-        let request = CreateEmbeddingRequestArgs::default()
-            .model(&self.model.to_string())
-            .input(prompt)
-            .build()?;
+        let mut request = CreateEmbeddingRequestArgs::default();
+        request.model(self.model.to_string()).input(prompt);
+        if let Some(dimensions) = self.dimensions {
+            request.dimensions(dimensions);
+        }
+        let request = request.build()?;
 
-        let response = client.embeddings().create(request).await?;
+        let response = self
+            .retry
+            .retry(|| async { Ok(client.embeddings().create(request.clone()).await?) })
+            .await?;
 
         let embedding = response.data.into_iter().nth(0).unwrap();
 
@@ -237,8 +1056,12 @@ impl<T> Embed for Arc<T>
 where
     T: Embed + Sync + Send,
 {
-    type Tokenizer = OpenAiTiktoken;
-    const DIM: u32 = 1536;
+    type Tokenizer = T::Tokenizer;
+
+    fn dim(&self) -> usize {
+        (**self).dim()
+    }
+
     async fn embed<I: Input + ?Sized>(&self, input: &I) -> Result<Vec<f32>> {
         (**self).embed(input).await
     }
@@ -248,7 +1071,11 @@ where
 mod tests {
 
     use super::*;
-    use crate::{lines, prompt, tokenizers::Tokenizer};
+    use crate::{
+        io::{Image, Multimodal},
+        lines, prompt,
+        tokenizers::Tokenizer,
+    };
     use serde::{Deserialize, Serialize};
 
     #[tokio::test]
@@ -266,7 +1093,7 @@ mod tests {
         std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
 
         let gpt35 = OpenAiLlm::default();
-        let response: RawString = gpt35.generate("How are you doing").await?;
+        let _response: RawString = gpt35.generate("How are you doing").await?;
         Ok(())
     }
 
@@ -311,6 +1138,90 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_request_options() -> Result<()> {
+        let llm = OpenAiLlm::builder()
+            .temperature(0.0)
+            .max_tokens(1000)
+            .stop(vec!["\n".to_string()])
+            .logit_bias(BTreeMap::from([(50256, -100)]))
+            .build();
+        let overrides = GenerationOptions::builder()
+            .model("gpt-4")
+            .temperature(0.7)
+            .seed(42)
+            .build();
+
+        let request = llm.request("Hi", &overrides, 1)?.build()?;
+        assert_eq!(request.model, "gpt-4");
+        assert_eq!(request.temperature, Some(0.7));
+        assert_eq!(request.seed, Some(42));
+        assert!(matches!(&request.stop, Some(Stop::StringArray(stop)) if stop == &["\n"]));
+        let bias = request.logit_bias.as_ref().unwrap();
+        assert_eq!(bias["50256"], serde_json::json!(-100));
+        assert_eq!(llm.body(&request, &overrides)?["max_tokens"], 1000);
+
+        // Beyond what async-openai can hold.
+        let many = GenerationOptions::builder().max_tokens(100_000).build();
+        assert_eq!(llm.body(&request, &many)?["max_tokens"], 100_000);
+
+        let request = llm
+            .request("Hi", &GenerationOptions::default(), 1)?
+            .build()?;
+        assert_eq!(request.model, "gpt-3.5-turbo");
+        assert_eq!(request.temperature, Some(0.0));
+        assert_eq!(request.seed, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_embedding_dim() {
+        assert_eq!(OpenAiEmbedding::default().dim(), 1536);
+
+        let large = OpenAiEmbedding::builder()
+            .model("text-embedding-3-large".to_string())
+            .build();
+        assert_eq!(large.dim(), 3072);
+
+        let shortened = Arc::new(
+            OpenAiEmbedding::builder()
+                .model("text-embedding-3-large".to_string())
+                .dimensions(256)
+                .build(),
+        );
+        assert_eq!(shortened.dim(), 256);
+    }
+
+    #[test]
+    fn test_multimodal_request() -> Result<()> {
+        let input = Multimodal::new()
+            .text("What is in this image?")
+            .image(Image::from_url("https://example.com/cat.jpg").with_detail(ImageDetail::High));
+        let request = OpenAiLlm::default()
+            .request(&input, &GenerationOptions::default(), 1)?
+            .build()?;
+
+        let message = serde_json::to_value(&request.messages[0])?;
+        assert_eq!(
+            message["content"],
+            serde_json::json!([
+                { "type": "text", "text": "What is in this image?" },
+                {
+                    "type": "image_url",
+                    "image_url": { "url": "https://example.com/cat.jpg", "detail": "high" }
+                }
+            ])
+        );
+
+        // Text-only inputs are sent as plain text.
+        let request = OpenAiLlm::default()
+            .request("Hi", &GenerationOptions::default(), 1)?
+            .build()?;
+        let message = serde_json::to_value(&request.messages[0])?;
+        assert_eq!(message["content"], "Hi");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_stream() -> Result<()> {
         std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
@@ -319,9 +1230,487 @@ mod tests {
         let mut s: TokenStream = gpt35.generate("How are you doing").await?;
 
         while let Some(item) = s.next().await {
-            println!("{item}");
+            println!("{}", item?);
+        }
+
+        Ok(())
+    }
+
+    /// Serve the given `(status, body)` responses in order, one per
+    /// connection, on a local port. Returns the API base and a request counter.
+    async fn stub_server(
+        responses: Vec<(u16, String)>,
+    ) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        let responses = responses
+            .into_iter()
+            .map(|(status, body)| (status, "", body))
+            .collect();
+        stub_server_with_headers(responses).await
+    }
+
+    /// Like [`stub_server`], with extra `headers` lines, e.g.
+    /// `"Retry-After: 1\r\n"`, in each `(status, headers, body)` response.
+    async fn stub_server_with_headers(
+        responses: Vec<(u16, &'static str, String)>,
+    ) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_base = format!("http://{}/v1", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        tokio::spawn(async move {
+            for (status, extra_headers, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+
+                // Read the headers, then the body of the request.
+                let mut request = vec![];
+                let mut buffer = [0; 4096];
+                let end = loop {
+                    let n = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                };
+                let headers = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                let length: usize = headers
+                    .lines()
+                    .find_map(|l| l.strip_prefix("content-length:"))
+                    .map_or(0, |l| l.trim().parse().unwrap());
+                while request.len() < end + length {
+                    let n = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                }
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+                let response = format!(
+                    "HTTP/1.1 {status} Stub\r\nContent-Type: application/json\r\n{extra_headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (api_base, requests)
+    }
+
+    /// Stream a single token, then stall without closing the connection.
+    /// The receiver resolves once the client closes the connection.
+    async fn stalled_server() -> (String, tokio::sync::oneshot::Receiver<()>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_base = format!("http://{}/v1", listener.local_addr().unwrap());
+        let (closed, receiver) = tokio::sync::oneshot::channel();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let chunk = serde_json::to_string(&chunk("Hello").response).unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\ndata: {chunk}\n\n"
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+
+            // Drain the request until the client hangs up.
+            let mut buffer = [0; 4096];
+            while !matches!(socket.read(&mut buffer).await, Ok(0) | Err(_)) {}
+            let _ = closed.send(());
+        });
+
+        (api_base, receiver)
+    }
+
+    #[tokio::test]
+    async fn test_stream_closes_connection() -> Result<()> {
+        let closed_within = |closed| tokio::time::timeout(Duration::from_secs(5), closed);
+
+        // Timed out.
+        let (api_base, closed) = stalled_server().await;
+        let llm = OpenAiLlm::builder()
+            .api_base(api_base)
+            .idle_timeout(Duration::from_millis(50))
+            .build();
+        let tokens: Vec<Result<String>> = Generate::<TokenStream>::generate(&llm, "Hi")
+            .await?
+            .collect()
+            .await;
+        assert_eq!(tokens[0].as_ref().unwrap(), "Hello");
+        assert!(matches!(tokens[1], Err(AsimovError::Timeout(_))));
+        assert!(closed_within(closed).await.is_ok());
+
+        // Cancelled.
+        let (api_base, closed) = stalled_server().await;
+        let llm = OpenAiLlm::builder().api_base(api_base).build();
+        let mut tokens: TokenStream = llm.generate("Hi").await?;
+        assert_eq!(tokens.next().await.unwrap()?, "Hello");
+        let handle = tokens.cancel_handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            handle.cancel();
+        });
+        assert!(matches!(
+            tokens.next().await,
+            Some(Err(AsimovError::Cancelled))
+        ));
+        assert!(closed_within(closed).await.is_ok());
+
+        // Dropped.
+        let (api_base, closed) = stalled_server().await;
+        let llm = OpenAiLlm::builder().api_base(api_base).build();
+        let mut tokens: TokenStream = llm.generate("Hi").await?;
+        assert_eq!(tokens.next().await.unwrap()?, "Hello");
+        drop(tokens);
+        assert!(closed_within(closed).await.is_ok());
+        Ok(())
+    }
+
+    fn api_error(message: &str, kind: &str, code: &str) -> String {
+        serde_json::json!({
+            "error": { "message": message, "type": kind, "param": null, "code": code }
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_retry_rate_limits() -> Result<()> {
+        let rate_limit = api_error(
+            "Rate limit reached for requests. Please try again in 20ms.",
+            "requests",
+            "rate_limit_exceeded",
+        );
+        let server_error = api_error(
+            "The server had an error while processing your request.",
+            "server_error",
+            "",
+        );
+        let completion = serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1694268190,
+            "model": "gpt-3.5-turbo",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hi!" },
+                "finish_reason": "stop"
+            }]
+        })
+        .to_string();
+
+        let bad_gateway = "<html><h1>502 Bad Gateway</h1></html>".to_string();
+
+        let (api_base, requests) = stub_server(vec![
+            (429, rate_limit),
+            (500, server_error),
+            (502, bad_gateway),
+            (200, completion),
+        ])
+        .await;
+        let llm = OpenAiLlm::builder()
+            .api_base(api_base)
+            .retry(
+                RetryPolicy::builder()
+                    .max_attempts(4)
+                    .initial_backoff(Duration::from_millis(1))
+                    .build(),
+            )
+            .build();
+
+        let answer: RawString = llm.generate("Hello").await?;
+        assert_eq!(answer.0, "Hi!");
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_header() -> Result<()> {
+        // The headers take precedence over the message.
+        let rate_limit = api_error(
+            "Rate limit reached for requests. Please try again in 1ms.",
+            "requests",
+            "rate_limit_exceeded",
+        );
+        let events = format!(
+            "data: {}\n\ndata: [DONE]\n\n",
+            serde_json::to_string(&chunk("Hi").response)?
+        );
+
+        let (api_base, _) = stub_server_with_headers(vec![
+            (429, "retry-after-ms: 200\r\n", rate_limit.clone()),
+            (200, "", events),
+        ])
+        .await;
+        let llm = OpenAiLlm::builder()
+            .api_base(api_base)
+            .retry(
+                RetryPolicy::builder()
+                    .initial_backoff(Duration::from_millis(1))
+                    .build(),
+            )
+            .build();
+        let start = Instant::now();
+        let mut tokens: TokenStream = llm.generate("Hello").await?;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(tokens.next().await.unwrap()?, "Hi");
+
+        let (api_base, _) =
+            stub_server_with_headers(vec![(429, "Retry-After: 2\r\n", rate_limit)]).await;
+        let llm = OpenAiLlm::builder()
+            .api_base(api_base)
+            .retry(RetryPolicy::none())
+            .build();
+        let tokens: Result<TokenStream> = llm.generate("Hello").await;
+        assert!(matches!(&tokens, Err(e) if e.retry_after() == Some(Duration::from_secs(2))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_candidates() -> Result<()> {
+        let choice = |index: u8, answer: &str| {
+            serde_json::json!({
+                "index": index,
+                "message": { "role": "assistant", "content": answer },
+                "finish_reason": "stop"
+            })
+        };
+        let completion = serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1694268190,
+            "model": "gpt-3.5-turbo",
+            "choices": [choice(0, "4"), choice(1, "5"), choice(2, "4")]
+        })
+        .to_string();
+
+        let (api_base, _) = stub_server(vec![(200, completion)]).await;
+        let llm = OpenAiLlm::builder().api_base(api_base).n(3).build();
+
+        let answers: Candidates<u8> = llm.generate("What is 2 + 2?").await?;
+        assert_eq!(answers.0, [4, 5, 4]);
+        assert_eq!(answers.majority(), Some(4));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_logprobs() -> Result<()> {
+        let completion = serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1694268190,
+            "model": "gpt-3.5-turbo",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "false" },
+                "logprobs": { "content": [{
+                    "token": "false",
+                    "logprob": -0.5,
+                    "bytes": null,
+                    "top_logprobs": [
+                        { "token": "false", "logprob": -0.5, "bytes": null },
+                        { "token": "true", "logprob": -1.0, "bytes": null }
+                    ]
+                }]},
+                "finish_reason": "stop"
+            }]
+        })
+        .to_string();
+
+        let (api_base, _) = stub_server(vec![(200, completion)]).await;
+        let llm = OpenAiLlm::builder().api_base(api_base).build();
+
+        let answer: WithLogprobs<bool> = llm.generate("Is the sky green?").await?;
+        assert!(!answer.value);
+        assert_eq!(answer.tokens[0].top_logprobs.len(), 2);
+        let labels = answer.labels();
+        assert!((labels["false"] - (-0.5f64).exp()).abs() < 1e-6);
+        assert!((labels["true"] - (-1.0f64).exp()).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_permanent_errors() {
+        let quota = api_error(
+            "You exceeded your current quota.",
+            "insufficient_quota",
+            "insufficient_quota",
+        );
+        let rate_limit = api_error(
+            "Please try again in 1ms.",
+            "requests",
+            "rate_limit_exceeded",
+        );
+
+        let (api_base, requests) = stub_server(vec![(429, quota)]).await;
+        let llm = OpenAiLlm::builder().api_base(api_base).build();
+        let answer: Result<RawString> = llm.generate("Hello").await;
+        assert!(matches!(&answer, Err(e) if !e.is_transient()));
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let (api_base, requests) =
+            stub_server(vec![(429, rate_limit.clone()), (429, rate_limit)]).await;
+        let embedding = OpenAiEmbedding::builder()
+            .api_base(api_base)
+            .retry(RetryPolicy::builder().max_attempts(2).build())
+            .build();
+        let embedded = embedding.embed(&"Hello").await;
+        assert!(matches!(&embedded, Err(e) if e.retry_after() == Some(Duration::from_millis(1))));
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    fn chunk(content: &str) -> ChatChunk {
+        serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion.chunk",
+            "created": 1694268190,
+            "model": "gpt-3.5-turbo",
+            "choices": [{ "index": 0, "delta": { "content": content } }]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_error() {
+        let mut empty = chunk("");
+        empty.response.choices.clear();
+
+        let chunks = futures::stream::iter(vec![
+            Ok(chunk("Hello")),
+            Ok(empty),
+            Ok(chunk(" world")),
+            Err(OpenAIError::StreamError("connection reset".to_string())),
+            Ok(chunk("!")),
+        ]);
+
+        let tokens: Vec<Result<String>> = token_stream(chunks, Instant::now()).collect().await;
+
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[0].as_ref().unwrap(), "Hello");
+        assert_eq!(tokens[1].as_ref().unwrap(), " world");
+        assert!(matches!(
+            tokens[2],
+            Err(AsimovError::OpenAI(OpenAIError::StreamError(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_metadata() {
+        let mut last = chunk("");
+        last.response.choices[0].delta.content = None;
+        last.response.choices[0].finish_reason = Some(OpenAiFinishReason::Length);
+
+        let chunks = futures::stream::iter(vec![Ok(chunk("Hello")), Ok(last)]);
+        let mut stream = token_stream(chunks, Instant::now());
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), "Hello");
+        assert_eq!(stream.metadata(), None);
+        assert!(stream.next().await.is_none());
+
+        let metadata = stream.metadata().unwrap();
+        assert_eq!(metadata.model, "gpt-3.5-turbo");
+        assert_eq!(metadata.id.as_deref(), Some("chatcmpl-123"));
+        assert!(metadata.is_truncated());
+    }
+
+    #[tokio::test]
+    async fn test_chat_events() {
+        // A chunk split over two `data` lines, then an error event.
+        let chunk = serde_json::to_string(&chunk("Hi").response).unwrap();
+        let (first, rest) = chunk.split_at(chunk.find(',').unwrap() + 1);
+        let error = api_error("The server is overloaded.", "server_error", "");
+        let events = format!("data: {first}\ndata: {rest}\n\nevent: error\ndata: {error}\n\n");
+
+        let bytes: Vec<reqwest::Result<Vec<u8>>> = events
+            .as_bytes()
+            .chunks(7)
+            .map(|c| Ok(c.to_vec()))
+            .collect();
+        let chunks: Vec<_> = chat_events(futures::stream::iter(bytes)).collect().await;
+
+        assert_eq!(chunks.len(), 2);
+        let content = &chunks[0].as_ref().unwrap().response.choices[0]
+            .delta
+            .content;
+        assert_eq!(content.as_deref(), Some("Hi"));
+        assert!(matches!(
+            &chunks[1],
+            Err(OpenAIError::ApiError(e)) if e.message == "The server is overloaded."
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_error_status() -> Result<()> {
+        let bad_gateway = "<html><h1>502 Bad Gateway</h1></html>".to_string();
+        let events = format!(
+            "data: {}\n\ndata: [DONE]\n\n",
+            serde_json::to_string(&chunk("Hi").response)?
+        );
+
+        let (api_base, _) = stub_server(vec![(502, bad_gateway.clone())]).await;
+        let llm = OpenAiLlm::builder()
+            .api_base(api_base)
+            .retry(RetryPolicy::none())
+            .build();
+        let tokens: Result<TokenStream> = llm.generate("Hello").await;
+        match tokens {
+            Err(e @ AsimovError::OpenAIStatus { status: 502, .. }) => assert!(e.is_transient()),
+            _ => panic!("expected an error with the status of the response"),
         }
 
+        let (api_base, requests) = stub_server(vec![(502, bad_gateway), (200, events)]).await;
+        let llm = OpenAiLlm::builder()
+            .api_base(api_base)
+            .retry(
+                RetryPolicy::builder()
+                    .initial_backoff(Duration::from_millis(1))
+                    .build(),
+            )
+            .build();
+        let mut tokens: TokenStream = llm.generate("Hello").await?;
+        assert_eq!(tokens.next().await.unwrap()?, "Hi");
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_usage() -> Result<()> {
+        let usage = serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion.chunk",
+            "created": 1694268190,
+            "model": "gpt-3.5-turbo",
+            "choices": [],
+            "usage": { "prompt_tokens": 9, "completion_tokens": 1, "total_tokens": 10 }
+        });
+        let events = format!(
+            "data: {}\n\ndata: {usage}\n\ndata: [DONE]\n\n",
+            serde_json::to_string(&chunk("Hi").response)?
+        );
+
+        let (api_base, _) = stub_server(vec![(200, events)]).await;
+        let llm = OpenAiLlm::builder().api_base(api_base).build();
+        let mut tokens: TokenStream = llm.generate("Hello").await?;
+
+        assert_eq!(tokens.next().await.unwrap()?, "Hi");
+        assert!(tokens.next().await.is_none());
+        let usage = tokens.metadata().unwrap().usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 9);
+        assert_eq!(usage.total_tokens, 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_generation_metadata() -> Result<()> {
+        std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+
+        let gpt35 = OpenAiLlm::builder().temperature(0.0).build();
+        let generation: Generation<MyType> = gpt35
+            .generate(r#"Output the following, verbatim: {"test": 1}"#)
+            .await?;
+
+        assert_eq!(generation.value, MyType { test: 1 });
+        assert_eq!(generation.metadata.finish_reason, Some(FinishReason::Stop));
+        assert!(generation.metadata.usage.unwrap().prompt_tokens > 0);
         Ok(())
     }
 
@@ -374,6 +1763,26 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_json_stream_array() -> Result<()> {
+        std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+        let gpt35 = OpenAiLlm::builder()
+            .temperature(0.0)
+            .json_stream(JsonStreamOptions::builder().unwrap_array(true).build())
+            .build();
+
+        let mut stream: StreamedOutput<MyType> = gpt35
+            .generate(r#"Output the following in a markdown code block, verbatim: [{"test": 1}, {"test": 2}]"#)
+            .await?;
+
+        let mut items = vec![];
+        while let Some(item) = stream.next().await {
+            items.push(item?);
+        }
+        assert_eq!(items, [MyType { test: 1 }, MyType { test: 2 }]);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_json_stream_single_item() -> Result<()> {
         std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
@@ -399,6 +1808,39 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_partial_stream_single_item() -> Result<()> {
+        std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+        let gpt35 = OpenAiLlm::builder().temperature(0.0).build();
+
+        let sample = PythonCode {
+            code: "import numpy as np\nx = np.array([1, 2, 3])\nprint(x)".to_string(),
+        };
+
+        let prompt = prompt!(
+            lines! {
+                "Output the following, verbatim: {{sample}}"
+            },
+            sample
+        );
+
+        let mut stream: StreamedOutput<Partial<PythonCode>> = gpt35.generate(prompt).await?;
+        let mut snapshots = 0;
+        let mut last = None;
+        while let Some(snapshot) = stream.next().await {
+            let snapshot = snapshot?;
+            println!("Snapshot: {:?}", snapshot.value);
+            snapshots += 1;
+            last = Some(snapshot);
+        }
+
+        let last = last.unwrap();
+        assert!(snapshots > 1);
+        assert!(last.complete);
+        assert_eq!(last.value, sample);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_token_stream_client_buffer() -> Result<()> {
         std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
@@ -421,7 +1863,7 @@ mod tests {
 
         while let Some(token) = token_stream.next().await {
             println!("Token: {:?}", token);
-            full_text.push_str(&token);
+            full_text.push_str(&token?);
         }
 
         let deserialized: PythonCode = serde_json::from_str(&full_text)?;
@@ -454,7 +1896,7 @@ mod tests {
 
         while let Some(token) = token_stream.next().await {
             println!("Token: {:?}", token);
-            buffer.extend_from_slice(token.as_bytes());
+            buffer.extend_from_slice(token?.as_bytes());
         }
 
         let full_text = String::from_utf8(buffer)
@@ -521,7 +1963,7 @@ mod tests {
 
         let sentiment: Sentiment = sentiment_analysis_llm.generate(sentiment_prompt).await?;
         let sent: bool = sentiment.sentiment;
-        assert_eq!(sent, true);
+        assert!(sent);
 
         let summary_prompt = prompt!(
             lines! {
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use typed_builder::TypedBuilder;
+
+/// Sampling settings of a generation.
+///
+/// Passed to [`Generate::generate_with`](crate::models::Generate::generate_with),
+/// the fields that are set override the settings of the model for a single
+/// call; the others keep the value configured on the model.
+///
+/// ```ignore
+/// let creative = GenerationOptions::builder().temperature(1.2).seed(42).build();
+/// let story: RawString = llm.generate_with("Tell me a story", &creative).await?;
+/// ```
+#[derive(TypedBuilder, Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GenerationOptions {
+    #[builder(default, setter(strip_option, into))]
+    /// Model name
+    pub model: Option<String>,
+    #[builder(default, setter(strip_option))]
+    /// Stop after `max_tokens` tokens
+    pub max_tokens: Option<u32>,
+    #[builder(default, setter(strip_option))]
+    /// Stopping criterion: stop generation upon any of these sequences
+    pub stop: Option<Vec<String>>,
+    #[builder(default, setter(strip_option))]
+    /// Sampling temperature, from 0.0 to 2.0
+    pub temperature: Option<f32>,
+    #[builder(default, setter(strip_option))]
+    /// Nucleus sampling: only sample from the tokens within the top `top_p`
+    /// probability mass
+    pub top_p: Option<f32>,
+    #[builder(default, setter(strip_option))]
+    /// Penalize the tokens already present in the output, from -2.0 to 2.0
+    pub presence_penalty: Option<f32>,
+    #[builder(default, setter(strip_option))]
+    /// Penalize the tokens in proportion to their frequency in the output,
+    /// from -2.0 to 2.0
+    pub frequency_penalty: Option<f32>,
+    #[builder(default, setter(strip_option))]
+    /// Bias added to the logits of the given token ids, from -100 to 100
+    pub logit_bias: Option<BTreeMap<u32, i32>>,
+    #[builder(default, setter(strip_option))]
+    /// Seed for a (mostly) deterministic sampling
+    pub seed: Option<i64>,
+    #[builder(default, setter(strip_option))]
+    /// Number of answers generated for [`Candidates`](crate::io::Candidates)
+    /// outputs
+    pub n: Option<u8>,
+    #[builder(default, setter(strip_option))]
+    /// Number of alternatives returned for each token of
+    /// [`WithLogprobs`](crate::io::WithLogprobs) outputs, up to 20
+    pub top_logprobs: Option<u8>,
+    #[builder(default, setter(strip_option, into))]
+    /// Identifier of the end user, for abuse monitoring
+    pub user: Option<String>,
+}
+
+impl GenerationOptions {
+    /// Whether no setting is overridden.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// These options, with the settings of `overrides` taking precedence.
+    pub fn merge(&self, overrides: &GenerationOptions) -> GenerationOptions {
+        let overrides = overrides.clone();
+        GenerationOptions {
+            model: overrides.model.or_else(|| self.model.clone()),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            stop: overrides.stop.or_else(|| self.stop.clone()),
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            logit_bias: overrides.logit_bias.or_else(|| self.logit_bias.clone()),
+            seed: overrides.seed.or(self.seed),
+            n: overrides.n.or(self.n),
+            top_logprobs: overrides.top_logprobs.or(self.top_logprobs),
+            user: overrides.user.or_else(|| self.user.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let model = GenerationOptions::builder()
+            .model("gpt-4")
+            .temperature(0.0)
+            .stop(vec!["\n".to_string()])
+            .build();
+        let call = GenerationOptions::builder()
+            .temperature(1.0)
+            .seed(7)
+            .build();
+
+        let merged = model.merge(&call);
+        assert_eq!(merged.model.as_deref(), Some("gpt-4"));
+        assert_eq!(merged.temperature, Some(1.0));
+        assert_eq!(merged.seed, Some(7));
+        assert_eq!(merged.stop, Some(vec!["\n".to_string()]));
+
+        assert_eq!(model.merge(&GenerationOptions::default()), model);
+        assert!(GenerationOptions::default().is_empty());
+        assert!(!call.is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+use async_trait::async_trait;
+
+use crate::{
+    db::space::VectorSpace,
+    error::Result,
+    io::{render_template, Input, RawString},
+    models::{Generate, GenerationOptions},
+    tokenizers::{openai::OpenAiTiktoken, Tokenizer},
+};
+
+const TEMPLATE: &str = "\
+Answer the question using only the sources below. Cite the sources you use \
+with their number in brackets, e.g. [1]. If the sources do not contain the \
+answer, say that you don't know.
+
+Sources:
+{{ context }}
+
+Question: {{ question }}
+Answer:";
+
+const REWRITE_TEMPLATE: &str = "\
+Rewrite the question below as a short search query for a document database. \
+Answer with the query only.
+
+Question: {{ question }}
+Query:";
+
+/// Answer of a [`Rag`] pipeline, along with the sources it cited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RagAnswer<I> {
+    /// Answer of the LLM, citations included.
+    pub answer: String,
+    /// Items cited by the answer, in the order they are first cited.
+    pub sources: Vec<I>,
+}
+
+/// Retrieval-augmented generation: answer questions from the items of a
+/// [`VectorSpace`] namespace.
+///
+/// For each question, the pipeline:
+/// 1. optionally asks the LLM to rewrite the question as a search query,
+/// 2. retrieves the `k` nearest items to the query,
+/// 3. numbers the items and packs as many as fit in `max_context_tokens`,
+///    most relevant first,
+/// 4. renders the Tera `template` with the `context` and the `question`,
+/// 5. generates the answer and looks up the sources it cites, e.g. `[2]`.
+///
+/// ```ignore
+/// let rag = Rag::new(db, OpenAiLlm::default(), "docs").k(8).rewrite_query();
+/// let answer: RagAnswer<Chunk> = rag.generate("How do I enable streaming?").await?;
+/// for source in &answer.sources {
+///     println!("{}", source.id());
+/// }
+/// ```
+///
+/// Generation options apply to both the rewriting and the answer.
+pub struct Rag<V, M, K = OpenAiTiktoken> {
+    pub space: V,
+    pub model: M,
+    namespace: String,
+    template: String,
+    rewrite_template: Option<String>,
+    k: usize,
+    max_context_tokens: usize,
+    tokenizer: K,
+}
+
+impl<V, M> Rag<V, M> {
+    /// Answer from the items of `namespace`, counting tokens with
+    /// [`OpenAiTiktoken`].
+    pub fn new(space: V, model: M, namespace: impl Into<String>) -> Self {
+        Self::with_tokenizer(space, model, namespace, OpenAiTiktoken::new())
+    }
+}
+
+impl<V, M, K: Tokenizer> Rag<V, M, K> {
+    pub fn with_tokenizer(space: V, model: M, namespace: impl Into<String>, tokenizer: K) -> Self {
+        Self {
+            space,
+            model,
+            namespace: namespace.into(),
+            template: TEMPLATE.to_string(),
+            rewrite_template: None,
+            k: 4,
+            max_context_tokens: 2000,
+            tokenizer,
+        }
+    }
+
+    /// Number of items retrieved for each question. Defaults to 4.
+    pub fn k(mut self, k: usize) -> Self {
+        self.k = k;
+        self
+    }
+
+    /// Maximum number of tokens of the sources in the prompt. Defaults to 2000.
+    pub fn max_context_tokens(mut self, tokens: usize) -> Self {
+        self.max_context_tokens = tokens;
+        self
+    }
+
+    /// Prompt with a Tera `template` instead, given the numbered sources as
+    /// `context` and the `question`.
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Ask the LLM to rewrite each question as a search query before
+    /// retrieving items.
+    pub fn rewrite_query(self) -> Self {
+        self.rewrite_template(REWRITE_TEMPLATE)
+    }
+
+    /// Rewrite each question with a Tera `template` instead, given the
+    /// `question`.
+    pub fn rewrite_template(mut self, template: impl Into<String>) -> Self {
+        self.rewrite_template = Some(template.into());
+        self
+    }
+
+    /// Keep the items that fit in the token budget, most relevant first,
+    /// along with the numbered context they make up.
+    fn pack<I: Input>(&self, items: Vec<I>) -> Result<(Vec<I>, String)> {
+        let mut packed = vec![];
+        let mut context = String::new();
+        let mut tokens = 0;
+
+        for item in items {
+            let source = format!("[{}] {}\n", packed.len() + 1, item.render()?);
+            let length = self.tokenizer.length(&source);
+            if tokens + length > self.max_context_tokens {
+                continue;
+            }
+            tokens += length;
+            context.push_str(&source);
+            packed.push(item);
+        }
+        Ok((packed, context))
+    }
+}
+
+/// Numbers cited in `answer`, e.g. `[1]` or `[2, 3]`, in order of first
+/// citation.
+fn citations(answer: &str) -> Vec<usize> {
+    let mut cited = vec![];
+    for (_, rest) in answer
+        .match_indices('[')
+        .map(|(i, _)| answer.split_at(i + 1))
+    {
+        let Some((inside, _)) = rest.split_once(']') else {
+            break;
+        };
+        let numbers: Option<Vec<usize>> =
+            inside.split(',').map(|n| n.trim().parse().ok()).collect();
+        for number in numbers.unwrap_or_default() {
+            if !cited.contains(&number) {
+                cited.push(number);
+            }
+        }
+    }
+    cited
+}
+
+#[async_trait]
+impl<V, M, K> Generate<RagAnswer<V::Item>> for Rag<V, M, K>
+where
+    V: VectorSpace + Send + Sync,
+    V::Item: Send,
+    M: Generate<RawString> + Send + Sync,
+    K: Tokenizer,
+{
+    async fn generate_with(
+        &self,
+        input: impl Input,
+        options: &GenerationOptions,
+    ) -> Result<RagAnswer<V::Item>> {
+        let question = input.render()?;
+
+        let query = match &self.rewrite_template {
+            Some(template) => {
+                let prompt = render_template(template, &[("question", &question)])?;
+                let query = self.model.generate_with(prompt, options).await?;
+                query.0.trim().to_string()
+            }
+            None => question.clone(),
+        };
+
+        let items = self.space.knn(&self.namespace, &query, self.k).await?;
+        let (items, context) = self.pack(items)?;
+
+        let prompt = render_template(
+            &self.template,
+            &[("context", context.trim_end()), ("question", &question)],
+        )?;
+        let answer = self.model.generate_with(prompt, options).await?.0;
+
+        let mut items: Vec<Option<V::Item>> = items.into_iter().map(Some).collect();
+        let sources = citations(&answer)
+            .into_iter()
+            .filter_map(|n| items.get_mut(n.checked_sub(1)?)?.take())
+            .collect();
+
+        Ok(RagAnswer { answer, sources })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::hora::HoraDb, models::mocks::Letters};
+
+    /// Rewrites questions to "paris", and answers by citing the sources
+    /// mentioning Paris.
+    struct Librarian;
+
+    #[async_trait]
+    impl Generate<RawString> for Librarian {
+        async fn generate_with(
+            &self,
+            input: impl Input,
+            _: &GenerationOptions,
+        ) -> Result<RawString> {
+            let prompt = input.render()?;
+            if prompt.starts_with("Rewrite") {
+                return Ok(RawString::new(" paris \n".to_string()));
+            }
+            let cited: Vec<&str> = prompt
+                .lines()
+                .filter(|line| line.starts_with('[') && line.contains("Paris"))
+                .filter_map(|line| line.split_once(' ').map(|(number, _)| number))
+                .collect();
+            Ok(RawString::new(format!("Paris {} [42]", cited.join(""))))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rag() -> Result<()> {
+        let mut db = HoraDb::new(Letters::default());
+        db.create_namespace("facts").await?;
+        db.add_items(
+            "facts",
+            vec![
+                "Paris is the capital of France.".to_string(),
+                "Bananas are yellow.".to_string(),
+                "The Eiffel tower is in Paris.".to_string(),
+            ],
+        )
+        .await?;
+
+        let rag = Rag::new(db, Librarian, "facts").k(3).rewrite_query();
+        let answer = rag.generate("Where is the Louvre?").await?;
+        assert_eq!(answer.sources.len(), 2);
+        assert!(answer.sources.iter().all(|s| s.contains("Paris")));
+        assert!(answer.answer.ends_with("[42]"));
+
+        let rag = rag.max_context_tokens(0);
+        let answer: RagAnswer<String> = rag.generate("Where is the Louvre?").await?;
+        assert!(answer.sources.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_citations() {
+        assert_eq!(citations("A [2], B [1, 3] and [2]."), [2, 1, 3]);
+        assert_eq!(citations("See [note] and [4"), Vec::<usize>::new());
+    }
+}
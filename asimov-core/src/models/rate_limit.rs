@@ -0,0 +1,324 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+use typed_builder::TypedBuilder;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::{text_content, Input},
+    models::{CacheConfig, Embed, Generate, GenerationOptions},
+    tokenizers::{openai::OpenAiTiktoken, Tokenizer},
+};
+
+/// Budget of a [`RateLimiter`], usually the limits of the provider account.
+#[derive(TypedBuilder, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    /// Maximum number of requests per minute, unlimited if `None`.
+    #[builder(default, setter(strip_option))]
+    pub requests_per_minute: Option<u32>,
+    /// Maximum number of tokens per minute, unlimited if `None`.
+    #[builder(default, setter(strip_option))]
+    pub tokens_per_minute: Option<u32>,
+}
+
+/// Token bucket refilled continuously, up to its per-minute capacity.
+struct Bucket {
+    capacity: f64,
+    available: f64,
+}
+
+impl Bucket {
+    fn new(per_minute: u32) -> Self {
+        Self {
+            capacity: per_minute as f64,
+            available: per_minute as f64,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        let refilled = self.capacity * elapsed.as_secs_f64() / 60.0;
+        self.available = (self.available + refilled).min(self.capacity);
+    }
+
+    /// Time until `cost` is available. Costs above the capacity wait for a full bucket.
+    fn wait(&self, cost: f64) -> Duration {
+        let missing = cost.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.capacity * 60.0)
+        }
+    }
+
+    fn consume(&mut self, cost: f64) {
+        self.available -= cost.min(self.capacity);
+    }
+}
+
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    updated: Instant,
+}
+
+impl Buckets {
+    /// The limited buckets, with the cost of the request for each.
+    fn limited(&mut self, tokens: f64) -> impl Iterator<Item = (&mut Bucket, f64)> {
+        [
+            (self.requests.as_mut(), 1.0),
+            (self.tokens.as_mut(), tokens),
+        ]
+        .into_iter()
+        .filter_map(|(bucket, cost)| Some((bucket?, cost)))
+    }
+
+    /// Consume a request of `tokens` tokens, or return how long to wait for it.
+    fn try_acquire(&mut self, tokens: f64) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now - self.updated;
+        self.updated = now;
+
+        let mut wait = Duration::ZERO;
+        for (bucket, cost) in self.limited(tokens) {
+            bucket.refill(elapsed);
+            wait = wait.max(bucket.wait(cost));
+        }
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+
+        for (bucket, cost) in self.limited(tokens) {
+            bucket.consume(cost);
+        }
+        None
+    }
+}
+
+/// Client-side limit on the requests and tokens sent per minute.
+///
+/// Clones share the same budget, so a single limiter can be handed to every
+/// model using the same account. Calls exceeding the budget wait in line
+/// until it is replenished, instead of failing with a rate limit error.
+#[derive(Clone)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<Buckets>>,
+    /// Callers wait for their turn in order.
+    queue: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl RateLimiter {
+    /// Limiter with the budget of `limits`, which must be above zero.
+    pub fn new(limits: RateLimits) -> Result<Self> {
+        if limits.requests_per_minute == Some(0) || limits.tokens_per_minute == Some(0) {
+            return Err(AsimovError::Model(
+                "Rate limits must allow at least 1 per minute".to_string(),
+            ));
+        }
+
+        let buckets = Buckets {
+            requests: limits.requests_per_minute.map(Bucket::new),
+            tokens: limits.tokens_per_minute.map(Bucket::new),
+            updated: Instant::now(),
+        };
+
+        Ok(Self {
+            buckets: Arc::new(Mutex::new(buckets)),
+            queue: Default::default(),
+        })
+    }
+
+    /// Wait until a request of `tokens` tokens fits in the budget, and consume it.
+    ///
+    /// Requests above the per-minute token limit wait for the whole budget.
+    pub async fn acquire(&self, tokens: usize) {
+        let _turn = self.queue.lock().await;
+        loop {
+            let wait = self.buckets.lock().try_acquire(tokens as f64);
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+}
+
+/// Limit the requests of any model with a [`RateLimiter`].
+///
+/// The tokens of each request are estimated with `tokenizer`, from the
+/// text of the input plus the expected length of the answer. Images are
+/// not counted.
+///
+/// ```ignore
+/// let limiter = RateLimiter::new(RateLimits::builder().tokens_per_minute(90_000).build())?;
+/// let gpt4 = RateLimited::new(OpenAiLlm::builder().model("gpt-4".into()).build(), limiter.clone());
+/// let gpt4_creative = RateLimited::new(OpenAiLlm::builder().model("gpt-4".into()).temperature(1.0).build(), limiter);
+/// ```
+#[derive(Clone)]
+pub struct RateLimited<M, K = OpenAiTiktoken> {
+    pub model: M,
+    limiter: RateLimiter,
+    tokenizer: K,
+    completion_tokens: usize,
+}
+
+impl<M> RateLimited<M> {
+    /// Limit `model`, counting tokens with [`OpenAiTiktoken`].
+    pub fn new(model: M, limiter: RateLimiter) -> Self {
+        Self::with_tokenizer(model, limiter, OpenAiTiktoken::new())
+    }
+}
+
+impl<M, K: Tokenizer> RateLimited<M, K> {
+    pub fn with_tokenizer(model: M, limiter: RateLimiter, tokenizer: K) -> Self {
+        Self {
+            model,
+            limiter,
+            tokenizer,
+            completion_tokens: 0,
+        }
+    }
+
+    /// Number of tokens expected in each answer, e.g. the `max_tokens` of the
+    /// model. The `max_tokens` of the generation options take precedence.
+    pub fn completion_tokens(mut self, tokens: usize) -> Self {
+        self.completion_tokens = tokens;
+        self
+    }
+
+    async fn acquire<I: Input + ?Sized>(
+        &self,
+        input: &I,
+        options: &GenerationOptions,
+    ) -> Result<()> {
+        let prompt = text_content(&input.content()?);
+        let completion_tokens = options
+            .max_tokens
+            .map(|t| t as usize)
+            .unwrap_or(self.completion_tokens);
+        let tokens = self.tokenizer.length(&prompt) + completion_tokens;
+        self.limiter.acquire(tokens).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T, M, K> Generate<T> for RateLimited<M, K>
+where
+    T: Send,
+    M: Generate<T> + Send + Sync,
+    K: Tokenizer,
+{
+    async fn generate_with(&self, input: impl Input, options: &GenerationOptions) -> Result<T> {
+        self.acquire(&input, options).await?;
+        self.model.generate_with(input, options).await
+    }
+}
+
+#[async_trait]
+impl<M: Embed, K: Tokenizer> Embed for RateLimited<M, K> {
+    type Tokenizer = M::Tokenizer;
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    async fn embed<I: Input + ?Sized>(&self, input: &I) -> Result<Vec<f32>> {
+        self.acquire(input, &GenerationOptions::default()).await?;
+        self.model.embed(input).await
+    }
+}
+
+impl<M: CacheConfig, K> CacheConfig for RateLimited<M, K> {
+    fn cache_config(&self) -> serde_json::Value {
+        self.model.cache_config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::RawString, models::mocks::Echo};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_per_minute() {
+        let limiter =
+            RateLimiter::new(RateLimits::builder().requests_per_minute(2).build()).unwrap();
+        let start = Instant::now();
+
+        limiter.acquire(0).await;
+        limiter.clone().acquire(0).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire(0).await;
+        assert_eq!(start.elapsed().as_secs(), 30);
+    }
+
+    #[test]
+    fn test_zero_limits() {
+        let no_requests = RateLimits::builder().requests_per_minute(0).build();
+        assert!(matches!(
+            RateLimiter::new(no_requests),
+            Err(AsimovError::Model(_))
+        ));
+        let no_tokens = RateLimits::builder().tokens_per_minute(0).build();
+        assert!(RateLimiter::new(no_tokens).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tokens_per_minute() {
+        let limiter =
+            RateLimiter::new(RateLimits::builder().tokens_per_minute(100).build()).unwrap();
+        let start = Instant::now();
+
+        limiter.acquire(60).await;
+        limiter.acquire(60).await;
+        assert_eq!(start.elapsed().as_secs(), 12);
+
+        // Above the limit: waits for the whole budget.
+        limiter.acquire(1000).await;
+        assert_eq!(start.elapsed().as_secs(), 72);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_completion_tokens() {
+        let limiter =
+            RateLimiter::new(RateLimits::builder().tokens_per_minute(100).build()).unwrap();
+        let llm = RateLimited::new(Echo::default(), limiter).completion_tokens(10);
+        let start = Instant::now();
+
+        let options = GenerationOptions::builder().max_tokens(60).build();
+        let _: RawString = llm.generate_with("", &options).await.unwrap();
+        let _: RawString = llm.generate_with("", &options).await.unwrap();
+        assert_eq!(start.elapsed().as_secs(), 12);
+
+        // Without `max_tokens`, the configured estimate is used.
+        let _: RawString = llm.generate("").await.unwrap();
+        assert_eq!(start.elapsed().as_secs(), 18);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limited_queue() {
+        let limiter =
+            RateLimiter::new(RateLimits::builder().requests_per_minute(60).build()).unwrap();
+        let llm = Arc::new(RateLimited::new(Echo::default(), limiter));
+        let start = Instant::now();
+
+        let tasks: Vec<_> = (0..90)
+            .map(|i| {
+                let llm = llm.clone();
+                tokio::spawn(async move {
+                    let answer: RawString = llm.generate(i.to_string()).await.unwrap();
+                    answer.0
+                })
+            })
+            .collect();
+
+        for (i, task) in tasks.into_iter().enumerate() {
+            assert_eq!(task.await.unwrap(), i.to_string());
+        }
+        // 60 requests right away, then one per second.
+        assert_eq!(start.elapsed().as_secs(), 30);
+    }
+}
@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::{
+    error::Result,
+    io::{render_template, Input, RawString},
+    models::{Generate, Rerank},
+};
+
+const TEMPLATE: &str = "\
+Rate how relevant the document is to the query, from 0 (unrelated) to 10 \
+(answers it fully). Answer with the number only.
+
+Query: {{ query }}
+
+Document: {{ document }}";
+
+/// Rerank documents by asking an LLM to rate each of them.
+///
+/// Documents are rated one request each, up to `concurrency` at a time (8 by
+/// default): rerank a few dozen candidates, not a whole collection. The
+/// score is the first number of the answer, e.g. 8 for "Score: 8/10";
+/// answers without a number score 0.
+///
+/// ```ignore
+/// let reranker = LlmReranker::new(OpenAiLlm::builder().temperature(0.0).build());
+/// let docs = db.knn_reranked("docs", &question, 5, 20, &reranker).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct LlmReranker<M> {
+    pub model: M,
+    template: String,
+    concurrency: usize,
+}
+
+impl<M> LlmReranker<M> {
+    pub fn new(model: M) -> Self {
+        Self {
+            model,
+            template: TEMPLATE.to_string(),
+            concurrency: 8,
+        }
+    }
+
+    /// Maximum number of documents rated at the same time. Defaults to 8.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Rate with a Tera `template` instead, given the `query` and the
+    /// `document`. The LLM must answer with a number.
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+}
+
+#[async_trait]
+impl<M> Rerank for LlmReranker<M>
+where
+    M: Generate<RawString> + Send + Sync,
+{
+    async fn score<Q, D>(&self, query: &Q, documents: &[D]) -> Result<Vec<f32>>
+    where
+        Q: Input + ?Sized,
+        D: Input,
+    {
+        let query = query.render()?;
+        let prompts = documents
+            .iter()
+            .map(|document| {
+                render_template(
+                    &self.template,
+                    &[("query", &query), ("document", &document.render()?)],
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let answers: Vec<RawString> = futures::stream::iter(prompts)
+            .map(|prompt| self.model.generate(prompt))
+            .buffered(self.concurrency)
+            .try_collect()
+            .await?;
+
+        Ok(answers
+            .iter()
+            .map(|answer| {
+                parse_score(answer).unwrap_or_else(|| {
+                    tracing::warn!("No score in the answer of the reranker: {answer}");
+                    0.0
+                })
+            })
+            .collect())
+    }
+}
+
+/// First number of `answer`, e.g. 8 for "Score: 8/10".
+fn parse_score(answer: &str) -> Option<f32> {
+    let start = answer.find(|c: char| c.is_ascii_digit())?;
+    let number: String = answer[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    number.trim_end_matches('.').parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::GenerationOptions;
+
+    /// Rates documents by the number of query words they contain, in
+    /// various formats, and gives no score to documents about python.
+    struct WordOverlap;
+
+    #[async_trait]
+    impl Generate<RawString> for WordOverlap {
+        async fn generate_with(
+            &self,
+            input: impl Input,
+            _: &GenerationOptions,
+        ) -> Result<RawString> {
+            let prompt = input.render()?;
+            let (_, rest) = prompt.split_once("Query: ").unwrap();
+            let (query, document) = rest.split_once("\n\nDocument: ").unwrap();
+            let overlap = query
+                .split(' ')
+                .filter(|word| document.split(' ').any(|w| w == *word))
+                .count();
+            let answer = match overlap {
+                _ if document.contains("python") => "I can't tell.".to_string(),
+                1 => "1".to_string(),
+                _ => format!("Score: {overlap}/10"),
+            };
+            Ok(RawString::new(answer))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_llm_reranker() -> Result<()> {
+        let reranker = LlmReranker::new(WordOverlap).concurrency(2);
+        let documents = vec![
+            "rust is fast".to_string(),
+            "the borrow checker in rust".to_string(),
+            "python is slow".to_string(),
+            "the rust borrow checker explained".to_string(),
+        ];
+
+        let scores = reranker.score(&"rust borrow checker", &documents).await?;
+        assert_eq!(scores, [1.0, 3.0, 0.0, 3.0]);
+
+        let top = reranker
+            .rerank(&"rust borrow checker", documents, 3)
+            .await?;
+        assert_eq!(
+            top,
+            [
+                "the borrow checker in rust",
+                "the rust borrow checker explained",
+                "rust is fast"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("8"), Some(8.0));
+        assert_eq!(parse_score("Score: 7.5/10."), Some(7.5));
+        assert_eq!(parse_score("It is a 9."), Some(9.0));
+        assert_eq!(parse_score("Not relevant"), None);
+    }
+}
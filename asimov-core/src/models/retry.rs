@@ -0,0 +1,278 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use typed_builder::TypedBuilder;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::Input,
+    models::{CacheConfig, Embed, Generate, GenerationOptions},
+};
+
+/// When and how often failed requests are sent again.
+///
+/// Only transient errors are retried, see
+/// [`AsimovError::is_transient`](crate::error::AsimovError::is_transient).
+/// The delay between attempts grows exponentially from `initial_backoff`,
+/// unless the provider asks for a specific delay (see
+/// [`AsimovError::retry_after`](crate::error::AsimovError::retry_after)).
+/// Either way, it is capped at `max_backoff`.
+#[derive(TypedBuilder, Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    #[builder(default = 3)]
+    pub max_attempts: u32,
+    #[builder(default = Duration::from_millis(500))]
+    pub initial_backoff: Duration,
+    #[builder(default = Duration::from_secs(30))]
+    pub max_backoff: Duration,
+    /// Factor applied to the delay after each attempt.
+    #[builder(default = 2.0)]
+    pub multiplier: f64,
+    /// Randomize the delays between half and all of their value, so that
+    /// concurrent clients don't retry in lockstep.
+    #[builder(default = true)]
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> Self {
+        Self::builder().max_attempts(1).build()
+    }
+
+    /// Delay before the attempt following the failed `attempt`, starting at 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self
+            .initial_backoff
+            .mul_f64(self.multiplier.powi(exponent).min(u32::MAX as f64))
+            .min(self.max_backoff);
+
+        if self.jitter {
+            // Random enough to spread retries, without pulling a RNG in.
+            let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+            delay.mul_f64(0.5 + random / 2.0)
+        } else {
+            delay
+        }
+    }
+
+    /// Delay before the attempt following the failed `attempt`, as requested
+    /// by the provider if it did, capped at `max_backoff`.
+    fn delay(&self, attempt: u32, error: &AsimovError) -> Duration {
+        match error.retry_after() {
+            Some(delay) => delay.min(self.max_backoff),
+            None => self.backoff(attempt),
+        }
+    }
+
+    /// Run `request` until it succeeds, fails with a permanent error, or
+    /// `max_attempts` is reached. The last error is returned.
+    pub async fn retry<T, F, Fut>(&self, mut request: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match request().await {
+                Err(e) if e.is_transient() && attempt < self.max_attempts => {
+                    let delay = self.delay(attempt, &e);
+                    tracing::warn!("Attempt {attempt} failed, retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Retry the requests of any model on transient errors.
+///
+/// ```ignore
+/// let llm = OpenAiLlm::builder().retry(RetryPolicy::none()).build();
+/// let llm = Retry::new(llm, RetryPolicy::default());
+/// let answer: RawString = llm.generate("How are you doing?").await?;
+/// ```
+///
+/// `OpenAiLlm` already retries its requests with its own `retry` policy, 3
+/// attempts by default: wrapping it as is multiplies the attempts, up to 9
+/// requests. Disable its policy as above, or use `Retry` for models without
+/// one.
+///
+/// Streams are retried until they are established: errors happening while
+/// tokens are streamed are passed to the consumer.
+#[derive(Debug, Clone)]
+pub struct Retry<M> {
+    pub model: M,
+    pub policy: RetryPolicy,
+}
+
+impl<M> Retry<M> {
+    pub fn new(model: M, policy: RetryPolicy) -> Self {
+        Self { model, policy }
+    }
+}
+
+#[async_trait]
+impl<T, M> Generate<T> for Retry<M>
+where
+    T: Send,
+    M: Generate<T> + Send + Sync,
+{
+    async fn generate_with(&self, input: impl Input, options: &GenerationOptions) -> Result<T> {
+        self.policy
+            .retry(|| self.model.generate_with(&input, options))
+            .await
+    }
+}
+
+#[async_trait]
+impl<M: Embed> Embed for Retry<M> {
+    type Tokenizer = M::Tokenizer;
+
+    fn dim(&self) -> usize {
+        self.model.dim()
+    }
+
+    async fn embed<I: Input + ?Sized>(&self, input: &I) -> Result<Vec<f32>> {
+        self.policy.retry(|| self.model.embed(input)).await
+    }
+}
+
+impl<M: CacheConfig> CacheConfig for Retry<M> {
+    fn cache_config(&self) -> serde_json::Value {
+        self.model.cache_config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::RawString;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails with the given errors, then answers.
+    struct Flaky {
+        errors: parking_lot::Mutex<Vec<AsimovError>>,
+        calls: AtomicU32,
+    }
+
+    impl Flaky {
+        fn new(errors: Vec<AsimovError>) -> Self {
+            Self {
+                errors: parking_lot::Mutex::new(errors),
+                calls: AtomicU32::new(0),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Generate<RawString> for Flaky {
+        async fn generate_with(
+            &self,
+            input: impl Input,
+            _: &GenerationOptions,
+        ) -> Result<RawString> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.errors.lock().pop() {
+                Some(e) => Err(e),
+                None => Ok(RawString::new(input.render()?)),
+            }
+        }
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::builder()
+            .max_attempts(max_attempts)
+            .initial_backoff(Duration::from_millis(100))
+            .build()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_transient() {
+        let flaky = Retry::new(
+            Flaky::new(vec![
+                AsimovError::Timeout(Duration::from_secs(1)),
+                AsimovError::Timeout(Duration::from_secs(1)),
+            ]),
+            policy(3),
+        );
+
+        let start = tokio::time::Instant::now();
+        let answer: RawString = flaky.generate("Hello").await.unwrap();
+        assert_eq!(answer.0, "Hello");
+        assert_eq!(flaky.model.calls.load(Ordering::SeqCst), 3);
+        // Two delays, of 50-100ms then 100-200ms.
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert!(start.elapsed() <= Duration::from_millis(300));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_gives_up() {
+        let timeout = || AsimovError::Timeout(Duration::from_secs(1));
+        let flaky = Retry::new(Flaky::new(vec![timeout(), timeout(), timeout()]), policy(2));
+        let answer: Result<RawString> = flaky.generate("Hello").await;
+        assert!(matches!(answer, Err(AsimovError::Timeout(_))));
+        assert_eq!(flaky.model.calls.load(Ordering::SeqCst), 2);
+
+        let flaky = Retry::new(Flaky::new(vec![AsimovError::Cancelled]), policy(3));
+        let answer: Result<RawString> = flaky.generate("Hello").await;
+        assert!(matches!(answer, Err(AsimovError::Cancelled)));
+        assert_eq!(flaky.model.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::builder()
+            .max_backoff(Duration::from_secs(3))
+            .jitter(false)
+            .build();
+        let delays: Vec<u128> = (1..=5).map(|a| policy.backoff(a).as_millis()).collect();
+        assert_eq!(delays, [500, 1000, 2000, 3000, 3000]);
+
+        let jittered = RetryPolicy::default().backoff(2);
+        assert!(jittered >= Duration::from_millis(500) && jittered <= Duration::from_secs(1));
+    }
+
+    #[cfg(feature = "openai")]
+    #[test]
+    fn test_retry_after_capped() {
+        let rate_limit = |message: &str| {
+            AsimovError::OpenAI(async_openai::error::OpenAIError::ApiError(
+                serde_json::from_value(serde_json::json!({
+                    "message": message,
+                    "type": "requests",
+                    "param": null,
+                    "code": "rate_limit_exceeded"
+                }))
+                .unwrap(),
+            ))
+        };
+        let policy = RetryPolicy::builder()
+            .max_backoff(Duration::from_secs(3))
+            .jitter(false)
+            .build();
+
+        let delay = |message| policy.delay(1, &rate_limit(message));
+        assert_eq!(
+            delay("Please try again in 1.5s."),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(delay("Please try again in 20m."), Duration::from_secs(3));
+        assert_eq!(delay("Rate limit reached."), Duration::from_millis(500));
+    }
+}
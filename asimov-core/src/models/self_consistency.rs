@@ -0,0 +1,124 @@
+use async_trait::async_trait;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::{Candidates, Input},
+    models::{Generate, GenerationOptions},
+};
+
+/// How [`SelfConsistency`] picks an answer among the candidates.
+pub trait Select<T>: Send + Sync {
+    fn select(&self, candidates: Candidates<T>) -> Option<T>;
+}
+
+/// Pick the most frequent answer, see [`Candidates::majority`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Majority;
+
+impl<T: PartialEq> Select<T> for Majority {
+    fn select(&self, candidates: Candidates<T>) -> Option<T> {
+        candidates.majority()
+    }
+}
+
+/// Pick the answer with the highest score, see [`Candidates::best_by`].
+impl<T, F> Select<T> for F
+where
+    F: Fn(&T) -> f64 + Send + Sync,
+{
+    fn select(&self, candidates: Candidates<T>) -> Option<T> {
+        candidates.best_by(self)
+    }
+}
+
+/// Sample `n` answers from a model and keep the most consistent one.
+///
+/// Voting among several sampled answers is usually more reliable than a
+/// single one, at the cost of `n` completions. Sample with a non-zero
+/// temperature, or all the answers will be the same.
+///
+/// ```ignore
+/// let llm = OpenAiLlm::builder().temperature(0.7).build();
+/// let classifier = SelfConsistency::new(llm, 5);
+/// let sentiment: Structured<Sentiment> = classifier.generate(review).await?;
+///
+/// // Or keep the answer with the highest score instead.
+/// let classifier = classifier.select_with(|answer: &Structured<Sentiment>| answer.confidence);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SelfConsistency<M, S = Majority> {
+    pub model: M,
+    pub n: u8,
+    selector: S,
+}
+
+impl<M> SelfConsistency<M> {
+    /// Keep the most frequent of `n` answers.
+    pub fn new(model: M, n: u8) -> Self {
+        Self {
+            model,
+            n,
+            selector: Majority,
+        }
+    }
+}
+
+impl<M, S> SelfConsistency<M, S> {
+    /// Pick the answer with `selector` instead.
+    pub fn select_with<S2>(self, selector: S2) -> SelfConsistency<M, S2> {
+        SelfConsistency {
+            model: self.model,
+            n: self.n,
+            selector,
+        }
+    }
+}
+
+#[async_trait]
+impl<T, M, S> Generate<T> for SelfConsistency<M, S>
+where
+    T: Send,
+    M: Generate<Candidates<T>> + Send + Sync,
+    S: Select<T>,
+{
+    async fn generate_with(&self, input: impl Input, options: &GenerationOptions) -> Result<T> {
+        let options = GenerationOptions {
+            n: Some(self.n),
+            ..options.clone()
+        };
+        let candidates = self.model.generate_with(input, &options).await?;
+        self.selector
+            .select(candidates)
+            .ok_or_else(|| AsimovError::Output("No candidates to select from".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::RawString,
+        models::{mocks::Echo, GenerateN},
+    };
+
+    #[tokio::test]
+    async fn test_self_consistency() -> Result<()> {
+        let llm = SelfConsistency::new(Echo::default(), 5);
+        let answer: RawString = llm.generate("yes no no yes no yes yes").await?;
+        assert_eq!(answer.0, "no");
+
+        let llm = llm.select_with(|answer: &RawString| answer.len() as f64);
+        let answer: RawString = llm.generate("a bb ccc dddd eeeee ffffff").await?;
+        assert_eq!(answer.0, "eeeee");
+
+        let answers = Echo::default().generate_n("a b c", 2).await?;
+        assert_eq!(
+            answers,
+            [
+                RawString::new("a".to_string()),
+                RawString::new("b".to_string())
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+//! # Text
+//!
+//! Utilities to prepare long documents before they are embedded.
+
+pub mod splitter;
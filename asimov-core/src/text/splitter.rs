@@ -0,0 +1,780 @@
+use std::{collections::VecDeque, ops::Range};
+
+use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
+
+use crate::{
+    error::{AsimovError, Result},
+    io::{Embeddable, Input},
+    tokenizers::Tokenizer,
+};
+
+/// A piece of a larger document, sized to be embedded on its own.
+///
+/// `start` and `end` are byte offsets into the original document, so that
+/// `&document[chunk.start..chunk.end] == chunk.text`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Identifier of the document the chunk was extracted from.
+    pub document_id: String,
+    /// Position of the chunk within its document.
+    pub index: usize,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    /// Markdown headers the chunk is nested under, outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<String>,
+}
+
+impl Chunk {
+    /// Identifier of the chunk, unique within a set of documents.
+    pub fn id(&self) -> String {
+        format!("{}#{}", self.document_id, self.index)
+    }
+}
+
+impl Input for Chunk {
+    fn render(&self) -> Result<String> {
+        Ok(self.text.clone())
+    }
+}
+
+impl Embeddable for Chunk {
+    type Key = String;
+
+    fn key(&self) -> Self::Key {
+        self.text.clone()
+    }
+}
+
+/// Splits documents into [`Chunk`]s that can be added to a
+/// [`VectorSpace`](crate::db::space::VectorSpace).
+pub trait TextSplitter {
+    /// Split `text`, tagging every chunk with `document_id`.
+    fn split(&self, document_id: &str, text: &str) -> Result<Vec<Chunk>>;
+
+    /// Split the rendered representation of `input`.
+    fn split_input<I: Input + ?Sized>(&self, document_id: &str, input: &I) -> Result<Vec<Chunk>> {
+        self.split(document_id, &input.render()?)
+    }
+}
+
+fn default_chunk_size() -> usize {
+    512
+}
+
+fn default_chunk_overlap() -> usize {
+    64
+}
+
+fn default_separators() -> Vec<String> {
+    ["\n\n", "\n", " ", ""].map(String::from).to_vec()
+}
+
+/// Splits text on the first separator found, and recursively splits the
+/// pieces that are still too large with the following separators.
+///
+/// The empty separator splits between characters.
+#[derive(TypedBuilder)]
+pub struct RecursiveCharacterSplitter<T: Tokenizer> {
+    tokenizer: T,
+    /// Maximum number of tokens in a chunk.
+    #[builder(default = default_chunk_size())]
+    chunk_size: usize,
+    /// Number of tokens shared by consecutive chunks.
+    #[builder(default = default_chunk_overlap())]
+    chunk_overlap: usize,
+    #[builder(default = default_separators())]
+    separators: Vec<String>,
+}
+
+impl<T: Tokenizer> TextSplitter for RecursiveCharacterSplitter<T> {
+    fn split(&self, document_id: &str, text: &str) -> Result<Vec<Chunk>> {
+        let budget = Budget::new(
+            &self.tokenizer,
+            self.chunk_size,
+            self.chunk_overlap,
+            char::is_whitespace,
+        )?;
+
+        let mut pieces = Vec::new();
+        budget.split(text, 0..text.len(), &self.separators, &mut pieces);
+
+        let mut chunks = Chunks::new(document_id, text, budget.trim);
+        chunks.extend(budget.merge(text, &pieces), &[]);
+        Ok(chunks.finish())
+    }
+}
+
+/// Splits text on sentence boundaries and packs whole sentences into chunks.
+///
+/// Sentences end with `.`, `!` or `?` followed by whitespace, or with a line
+/// break. Sentences longer than the chunk size are split on words.
+#[derive(TypedBuilder)]
+pub struct SentenceSplitter<T: Tokenizer> {
+    tokenizer: T,
+    /// Maximum number of tokens in a chunk.
+    #[builder(default = default_chunk_size())]
+    chunk_size: usize,
+    /// Number of tokens shared by consecutive chunks.
+    #[builder(default = default_chunk_overlap())]
+    chunk_overlap: usize,
+}
+
+impl<T: Tokenizer> TextSplitter for SentenceSplitter<T> {
+    fn split(&self, document_id: &str, text: &str) -> Result<Vec<Chunk>> {
+        let budget = Budget::new(
+            &self.tokenizer,
+            self.chunk_size,
+            self.chunk_overlap,
+            char::is_whitespace,
+        )?;
+        let word_separators = [" ", ""].map(String::from);
+
+        let mut pieces = Vec::new();
+        for sentence in sentences(text) {
+            budget.split(text, sentence, &word_separators, &mut pieces);
+        }
+
+        let mut chunks = Chunks::new(document_id, text, budget.trim);
+        chunks.extend(budget.merge(text, &pieces), &[]);
+        Ok(chunks.finish())
+    }
+}
+
+/// Splits markdown documents along their headers.
+///
+/// Chunks never span two sections, and record the headers they are nested
+/// under in [`Chunk::headers`]. Sections larger than the chunk size are
+/// split on paragraphs, lines and words.
+#[derive(TypedBuilder)]
+pub struct MarkdownHeaderSplitter<T: Tokenizer> {
+    tokenizer: T,
+    /// Maximum number of tokens in a chunk.
+    #[builder(default = default_chunk_size())]
+    chunk_size: usize,
+    /// Number of tokens shared by consecutive chunks of the same section.
+    #[builder(default = default_chunk_overlap())]
+    chunk_overlap: usize,
+}
+
+impl<T: Tokenizer> TextSplitter for MarkdownHeaderSplitter<T> {
+    fn split(&self, document_id: &str, text: &str) -> Result<Vec<Chunk>> {
+        let budget = Budget::new(
+            &self.tokenizer,
+            self.chunk_size,
+            self.chunk_overlap,
+            char::is_whitespace,
+        )?;
+        let separators = default_separators();
+
+        let mut chunks = Chunks::new(document_id, text, budget.trim);
+        for (section, headers) in markdown_sections(text) {
+            let mut pieces = Vec::new();
+            budget.split(text, section, &separators, &mut pieces);
+            chunks.extend(budget.merge(text, &pieces), &headers);
+        }
+        Ok(chunks.finish())
+    }
+}
+
+/// Programming languages understood by [`CodeSplitter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Go,
+    Java,
+}
+
+impl Language {
+    /// Separators tried in order, from top-level definitions down to
+    /// single characters.
+    pub fn separators(&self) -> Vec<String> {
+        let separators: &[&str] = match self {
+            Language::Rust => &[
+                "\nimpl ",
+                "\npub fn ",
+                "\nfn ",
+                "\npub struct ",
+                "\nstruct ",
+                "\npub enum ",
+                "\nenum ",
+                "\npub trait ",
+                "\ntrait ",
+                "\nmod ",
+                "\n    pub fn ",
+                "\n    fn ",
+            ],
+            Language::Python => &[
+                "\nclass ",
+                "\ndef ",
+                "\nasync def ",
+                "\n    def ",
+                "\n\tdef ",
+            ],
+            Language::JavaScript => &[
+                "\nexport ",
+                "\nclass ",
+                "\nfunction ",
+                "\nconst ",
+                "\nlet ",
+                "\nvar ",
+                "\nif ",
+                "\nfor ",
+                "\nwhile ",
+                "\nswitch ",
+            ],
+            Language::TypeScript => &[
+                "\nexport ",
+                "\ninterface ",
+                "\ntype ",
+                "\nenum ",
+                "\nclass ",
+                "\nfunction ",
+                "\nconst ",
+                "\nlet ",
+                "\nvar ",
+                "\nif ",
+                "\nfor ",
+                "\nwhile ",
+                "\nswitch ",
+            ],
+            Language::Go => &[
+                "\nfunc ",
+                "\ntype ",
+                "\nvar ",
+                "\nconst ",
+                "\nif ",
+                "\nfor ",
+                "\nswitch ",
+            ],
+            Language::Java => &[
+                "\npublic ",
+                "\nprotected ",
+                "\nprivate ",
+                "\nclass ",
+                "\ninterface ",
+                "\nstatic ",
+                "\n    public ",
+                "\n    protected ",
+                "\n    private ",
+            ],
+        };
+
+        separators
+            .iter()
+            .chain(&["\n\n", "\n", " ", ""])
+            .map(|s| s.to_string())
+            .collect()
+    }
+}
+
+/// Splits source code along definitions (functions, classes, ...) before
+/// falling back to blank lines, lines and words.
+///
+/// Unlike the other splitters, only line breaks are trimmed from chunk
+/// boundaries, so that indentation is preserved.
+#[derive(TypedBuilder)]
+pub struct CodeSplitter<T: Tokenizer> {
+    tokenizer: T,
+    language: Language,
+    /// Maximum number of tokens in a chunk.
+    #[builder(default = default_chunk_size())]
+    chunk_size: usize,
+    /// Number of tokens shared by consecutive chunks.
+    #[builder(default = default_chunk_overlap())]
+    chunk_overlap: usize,
+}
+
+impl<T: Tokenizer> TextSplitter for CodeSplitter<T> {
+    fn split(&self, document_id: &str, text: &str) -> Result<Vec<Chunk>> {
+        let budget = Budget::new(
+            &self.tokenizer,
+            self.chunk_size,
+            self.chunk_overlap,
+            is_line_break,
+        )?;
+
+        let mut pieces = Vec::new();
+        budget.split(
+            text,
+            0..text.len(),
+            &self.language.separators(),
+            &mut pieces,
+        );
+
+        let mut chunks = Chunks::new(document_id, text, budget.trim);
+        chunks.extend(budget.merge(text, &pieces), &[]);
+        Ok(chunks.finish())
+    }
+}
+
+/// Characters trimmed from the boundaries of chunks.
+type Trim = fn(char) -> bool;
+
+fn is_line_break(c: char) -> bool {
+    c == '\n' || c == '\r'
+}
+
+/// Token budget shared by all splitters.
+struct Budget<'a, T: Tokenizer> {
+    tokenizer: &'a T,
+    chunk_size: usize,
+    chunk_overlap: usize,
+    trim: Trim,
+}
+
+impl<'a, T: Tokenizer> Budget<'a, T> {
+    fn new(tokenizer: &'a T, chunk_size: usize, chunk_overlap: usize, trim: Trim) -> Result<Self> {
+        if chunk_size == 0 {
+            return Err(AsimovError::Splitter(
+                "Chunk size must be greater than 0".to_string(),
+            ));
+        }
+        if chunk_overlap >= chunk_size {
+            return Err(AsimovError::Splitter(format!(
+                "Chunk overlap ({chunk_overlap}) must be smaller than the chunk size ({chunk_size})"
+            )));
+        }
+
+        Ok(Self {
+            tokenizer,
+            chunk_size,
+            chunk_overlap,
+            trim,
+        })
+    }
+
+    /// Number of tokens in `range`, once trimmed like the final chunk.
+    fn length(&self, text: &str, range: Range<usize>) -> usize {
+        self.tokenizer.length(text[range].trim_matches(self.trim))
+    }
+
+    /// Split `range` into contiguous pieces that fit in a chunk, using the
+    /// first separator present in the text and recursing with the following
+    /// ones. Separators stay attached to the start of the next piece.
+    fn split(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        separators: &[String],
+        out: &mut Vec<Range<usize>>,
+    ) {
+        if self.length(text, range.clone()) <= self.chunk_size {
+            out.push(range);
+            return;
+        }
+
+        let slice = &text[range.clone()];
+        let Some(position) = separators
+            .iter()
+            .position(|s| s.is_empty() || slice.contains(s.as_str()))
+        else {
+            // Nothing left to split on: keep the oversized piece as is.
+            out.push(range);
+            return;
+        };
+        let (separator, remaining) = (&separators[position], &separators[position + 1..]);
+
+        let mut boundaries: Vec<usize> = if separator.is_empty() {
+            slice.char_indices().map(|(i, _)| i).collect()
+        } else {
+            slice
+                .match_indices(separator.as_str())
+                .map(|(i, _)| i)
+                .collect()
+        };
+        boundaries.retain(|&i| i > 0);
+        boundaries.push(slice.len());
+
+        let mut start = 0;
+        for end in boundaries {
+            let piece = range.start + start..range.start + end;
+            if self.length(text, piece.clone()) <= self.chunk_size {
+                out.push(piece);
+            } else {
+                self.split(text, piece, remaining, out);
+            }
+            start = end;
+        }
+    }
+
+    /// Merge contiguous pieces into chunks of at most `chunk_size` tokens,
+    /// carrying the last pieces of a chunk over to the next one as overlap.
+    fn merge(&self, text: &str, pieces: &[Range<usize>]) -> Vec<Range<usize>> {
+        let mut chunks = Vec::new();
+        let mut window: VecDeque<Range<usize>> = VecDeque::new();
+
+        for piece in pieces {
+            if let (Some(first), Some(last)) = (window.front(), window.back()) {
+                if self.length(text, first.start..piece.end) > self.chunk_size {
+                    chunks.push(first.start..last.end);
+
+                    while let (Some(first), Some(last)) = (window.front(), window.back()) {
+                        let overlap = self.length(text, first.start..last.end);
+                        if overlap > self.chunk_overlap
+                            || self.length(text, first.start..piece.end) > self.chunk_size
+                        {
+                            window.pop_front();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+            }
+            window.push_back(piece.clone());
+        }
+
+        if let (Some(first), Some(last)) = (window.front(), window.back()) {
+            chunks.push(first.start..last.end);
+        }
+
+        chunks
+    }
+}
+
+/// Accumulates the chunks of a document, trimming their boundaries.
+struct Chunks<'a> {
+    document_id: &'a str,
+    text: &'a str,
+    trim: Trim,
+    chunks: Vec<Chunk>,
+}
+
+impl<'a> Chunks<'a> {
+    fn new(document_id: &'a str, text: &'a str, trim: Trim) -> Self {
+        Self {
+            document_id,
+            text,
+            trim,
+            chunks: Vec::new(),
+        }
+    }
+
+    fn extend(&mut self, ranges: Vec<Range<usize>>, headers: &[String]) {
+        for range in ranges {
+            let slice = &self.text[range.clone()];
+            let start = range.start + slice.len() - slice.trim_start_matches(self.trim).len();
+            let end = range.start + slice.trim_end_matches(self.trim).len();
+
+            if start >= end {
+                continue;
+            }
+
+            self.chunks.push(Chunk {
+                document_id: self.document_id.to_string(),
+                index: self.chunks.len(),
+                start,
+                end,
+                text: self.text[start..end].to_string(),
+                headers: headers.to_vec(),
+            });
+        }
+    }
+
+    fn finish(self) -> Vec<Chunk> {
+        self.chunks
+    }
+}
+
+/// Byte ranges of the sentences of `text`. Whitespace following a sentence
+/// belongs to the next one.
+fn sentences(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let mut end = i + c.len_utf8();
+
+        if matches!(c, '.' | '!' | '?') {
+            // Keep closing quotes and brackets with the sentence.
+            while let Some(&(j, next)) = chars.peek() {
+                if matches!(next, '"' | '\'' | ')' | ']' | '\u{201d}' | '\u{2019}') {
+                    end = j + next.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            if chars.peek().is_some_and(|&(_, next)| !next.is_whitespace()) {
+                continue;
+            }
+        } else if c != '\n' {
+            continue;
+        }
+
+        ranges.push(start..end);
+        start = end;
+    }
+
+    if start < text.len() {
+        ranges.push(start..text.len());
+    }
+
+    ranges
+}
+
+/// Level and title of an ATX markdown header (`## Title`).
+fn markdown_header(line: &str) -> Option<(usize, String)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+
+    let line = line[indent..].trim_end();
+    let level = line.len() - line.trim_start_matches('#').len();
+    let title = &line[level..];
+
+    if !(1..=6).contains(&level) || !(title.is_empty() || title.starts_with([' ', '\t'])) {
+        return None;
+    }
+
+    Some((
+        level,
+        title.trim().trim_end_matches('#').trim_end().to_string(),
+    ))
+}
+
+/// Byte ranges of the sections of a markdown document, along with the
+/// headers each section is nested under. Headers inside fenced code blocks
+/// are ignored.
+fn markdown_sections(text: &str) -> Vec<(Range<usize>, Vec<String>)> {
+    let mut sections = Vec::new();
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    let mut in_fence = false;
+
+    let titles = |stack: &[(usize, String)]| stack.iter().map(|(_, t)| t.clone()).collect();
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if !in_fence {
+            if let Some((level, title)) = markdown_header(line) {
+                if offset > start {
+                    sections.push((start..offset, titles(&stack)));
+                }
+                while stack.last().is_some_and(|(l, _)| *l >= level) {
+                    stack.pop();
+                }
+                stack.push((level, title));
+                start = offset;
+            }
+        }
+        offset += line.len();
+    }
+
+    if text.len() > start {
+        sections.push((start..text.len(), titles(&stack)));
+    }
+
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines;
+    use crate::tokenizers::openai::OpenAiTiktoken;
+
+    /// Counts whitespace-separated words, to keep sizes predictable.
+    struct WordTokenizer;
+
+    impl Tokenizer for WordTokenizer {
+        fn encode(&self, text: &str) -> Vec<usize> {
+            text.split_whitespace().map(|w| w.len()).collect()
+        }
+
+        fn decode(&self, _tokens: &[usize]) -> Result<String, AsimovError> {
+            Err(AsimovError::Tokenizer("Not supported".to_string()))
+        }
+
+        fn length(&self, text: &str) -> usize {
+            self.encode(text).len()
+        }
+    }
+
+    fn assert_offsets(text: &str, chunks: &[Chunk]) {
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk.index, index);
+            assert_eq!(&text[chunk.start..chunk.end], chunk.text);
+        }
+    }
+
+    #[test]
+    fn test_short_text_is_a_single_chunk() -> Result<()> {
+        let splitter = RecursiveCharacterSplitter::builder()
+            .tokenizer(WordTokenizer)
+            .chunk_size(10)
+            .chunk_overlap(2)
+            .build();
+
+        let chunks = splitter.split("doc", "  a short text\n")?;
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].text, "a short text");
+        assert_eq!(chunks[0].id(), "doc#0");
+        assert_offsets("  a short text\n", &chunks);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recursive_splitter_respects_size_and_overlap() -> Result<()> {
+        let text = "one two three four five six seven eight nine ten\n\neleven twelve thirteen";
+        let tokenizer = WordTokenizer;
+        let splitter = RecursiveCharacterSplitter::builder()
+            .tokenizer(WordTokenizer)
+            .chunk_size(4)
+            .chunk_overlap(1)
+            .build();
+
+        let chunks = splitter.split("doc", text)?;
+
+        assert_offsets(text, &chunks);
+        assert!(chunks.iter().all(|c| tokenizer.length(&c.text) <= 4));
+        assert_eq!(chunks[0].text, "one two three four");
+        assert_eq!(chunks[1].text, "four five six seven");
+        assert_eq!(chunks.last().unwrap().text, "ten\n\neleven twelve thirteen");
+        Ok(())
+    }
+
+    #[test]
+    fn test_recursive_splitter_with_tiktoken() -> Result<()> {
+        let text = "Asimov is a library for building high performance LLM-powered applications. "
+            .repeat(20);
+        let tokenizer = OpenAiTiktoken::new();
+        let splitter = RecursiveCharacterSplitter::builder()
+            .tokenizer(OpenAiTiktoken::new())
+            .chunk_size(32)
+            .chunk_overlap(8)
+            .build();
+
+        let chunks = splitter.split("doc", &text)?;
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| tokenizer.length(&c.text) <= 32));
+        assert_offsets(&text, &chunks);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbreakable_words_are_split_on_characters() -> Result<()> {
+        let text = "a".repeat(100);
+        let splitter = RecursiveCharacterSplitter::builder()
+            .tokenizer(OpenAiTiktoken::new())
+            .chunk_size(5)
+            .chunk_overlap(0)
+            .build();
+
+        let chunks = splitter.split("doc", &text)?;
+
+        assert!(chunks.len() > 1);
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<String>(),
+            text
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sentence_splitter_keeps_sentences_whole() -> Result<()> {
+        let text = "The first sentence is here. \"Is this the second?\" Yes! A fourth one follows";
+        let splitter = SentenceSplitter::builder()
+            .tokenizer(WordTokenizer)
+            .chunk_size(6)
+            .chunk_overlap(0)
+            .build();
+
+        let chunks = splitter.split("doc", text)?;
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+
+        assert_eq!(
+            texts,
+            vec![
+                "The first sentence is here.",
+                "\"Is this the second?\" Yes!",
+                "A fourth one follows"
+            ]
+        );
+        assert_offsets(text, &chunks);
+        Ok(())
+    }
+
+    #[test]
+    fn test_markdown_splitter_tracks_headers() -> Result<()> {
+        let text = lines! {
+            "Preamble.",
+            "# Title",
+            "Intro text.",
+            "## Install",
+            "```bash",
+            "# not a header",
+            "cargo add asimov",
+            "```",
+            "## Usage",
+            "Use it."
+        };
+        let splitter = MarkdownHeaderSplitter::builder()
+            .tokenizer(WordTokenizer)
+            .chunk_size(20)
+            .chunk_overlap(0)
+            .build();
+
+        let chunks = splitter.split("readme", &text)?;
+
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks[0].headers.is_empty());
+        assert_eq!(chunks[1].headers, vec!["Title"]);
+        assert_eq!(chunks[2].headers, vec!["Title", "Install"]);
+        assert!(chunks[2].text.contains("# not a header"));
+        assert_eq!(chunks[3].headers, vec!["Title", "Usage"]);
+        assert_offsets(&text, &chunks);
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_splitter_splits_on_definitions() -> Result<()> {
+        let text = lines! {
+            "fn first() {",
+            "    let x = 1;",
+            "}",
+            "",
+            "fn second() {",
+            "    let y = 2;",
+            "}"
+        };
+        let splitter = CodeSplitter::builder()
+            .tokenizer(WordTokenizer)
+            .language(Language::Rust)
+            .chunk_size(8)
+            .chunk_overlap(0)
+            .build();
+
+        let chunks = splitter.split("main.rs", &text)?;
+
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].text.starts_with("fn first()"));
+        assert!(chunks[1].text.starts_with("fn second()"));
+        assert!(chunks[1].text.contains("\n    let y = 2;"));
+        assert_offsets(&text, &chunks);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_overlap() {
+        let splitter = RecursiveCharacterSplitter::builder()
+            .tokenizer(WordTokenizer)
+            .chunk_size(4)
+            .chunk_overlap(4)
+            .build();
+
+        assert!(splitter.split("doc", "some text").is_err());
+    }
+}
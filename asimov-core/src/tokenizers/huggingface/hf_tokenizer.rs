@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use ::tokenizers::Tokenizer as HfTokenizerModel;
+
+use crate::{error::AsimovError, tokenizers::Tokenizer, Result};
+
+/// Tokenizer loaded from a HuggingFace `tokenizer.json` file.
+///
+/// Use it to count tokens for models that do not share OpenAI's vocabulary,
+/// e.g. Llama or Mistral checkpoints served locally.
+pub struct HfTokenizer {
+    tokenizer: HfTokenizerModel,
+}
+
+impl HfTokenizer {
+    /// Loads the tokenizer from a `tokenizer.json` file on disk.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let tokenizer = HfTokenizerModel::from_file(path).map_err(|e| {
+            AsimovError::Tokenizer(format!(
+                "Could not load tokenizer from {}: {e}",
+                path.display()
+            ))
+        })?;
+
+        Ok(Self { tokenizer })
+    }
+
+    /// Loads the tokenizer from the content of a `tokenizer.json` file.
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<Self> {
+        let tokenizer = HfTokenizerModel::from_bytes(bytes)
+            .map_err(|e| AsimovError::Tokenizer(format!("Could not load tokenizer: {e}")))?;
+
+        Ok(Self { tokenizer })
+    }
+}
+
+impl From<HfTokenizerModel> for HfTokenizer {
+    fn from(tokenizer: HfTokenizerModel) -> Self {
+        Self { tokenizer }
+    }
+}
+
+impl Tokenizer for HfTokenizer {
+    /// Special tokens (BOS, EOS, ...) are not added, so that the count only
+    /// reflects the text itself.
+    fn encode(&self, text: &str) -> Vec<usize> {
+        match self.tokenizer.encode(text, false) {
+            Ok(encoding) => encoding.get_ids().iter().map(|&id| id as usize).collect(),
+            Err(e) => {
+                tracing::warn!("HuggingFace tokenizer failed to encode text: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    fn decode(&self, tokens: &[usize]) -> Result<String, AsimovError> {
+        let ids = tokens
+            .iter()
+            .map(|&token| {
+                u32::try_from(token).map_err(|_| {
+                    AsimovError::Tokenizer(format!("Token id {token} is out of range"))
+                })
+            })
+            .collect::<Result<Vec<u32>>>()?;
+
+        self.tokenizer
+            .decode(&ids, false)
+            .map_err(|e| AsimovError::Tokenizer(e.to_string()))
+    }
+
+    fn length(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use ::tokenizers::{models::wordlevel::WordLevel, pre_tokenizers::whitespace::Whitespace};
+
+    use super::*;
+
+    fn word_level_tokenizer() -> HfTokenizerModel {
+        let vocab: HashMap<String, u32> =
+            [("[UNK]", 0), ("this", 1), ("is", 2), ("a", 3), ("test", 4)]
+                .into_iter()
+                .map(|(token, id)| (token.to_string(), id))
+                .collect();
+
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap();
+
+        let mut tokenizer = HfTokenizerModel::new(model);
+        tokenizer.with_pre_tokenizer(Whitespace {});
+        tokenizer
+    }
+
+    #[test]
+    fn test_hf_tokenizer_from_file() {
+        let path = std::env::temp_dir().join(format!("asimov-{}.json", uuid::Uuid::new_v4()));
+        word_level_tokenizer().save(&path, false).unwrap();
+
+        let tokenizer = HfTokenizer::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(tokenizer.encode("this is a test"), vec![1, 2, 3, 4]);
+        assert_eq!(tokenizer.length("this is unknown"), 3);
+        assert_eq!(tokenizer.decode(&[1, 2, 3, 4]).unwrap(), "this is a test");
+    }
+
+    #[test]
+    fn test_hf_tokenizer_missing_file() {
+        assert!(HfTokenizer::from_file("does/not/exist/tokenizer.json").is_err());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod hf_tokenizer;
+pub use hf_tokenizer::HfTokenizer;
@@ -17,3 +17,6 @@ pub trait Tokenizer: Send + Sync {
 }
 
 pub mod openai;
+
+#[cfg(feature = "huggingface")]
+pub mod huggingface;
@@ -0,0 +1,9 @@
+use asimov::prelude::*;
+
+#[asimov(key = name)]
+enum Animal {
+    Cat { name: String },
+    Dog { name: String },
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use asimov::prelude::*;
+
+#[asimov(key = title)]
+struct Document {
+    body: String,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use asimov::prelude::*;
+use serde::Deserialize;
+
+#[derive(Deserialize, AsimovOutput)]
+#[serde(content = "value", tag = "type")]
+enum Shape {
+    Circle(f64),
+    Square(f64),
+}
+
+fn main() {}
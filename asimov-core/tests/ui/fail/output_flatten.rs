@@ -0,0 +1,16 @@
+use asimov::prelude::*;
+use serde::Deserialize;
+
+#[derive(Deserialize, AsimovOutput)]
+struct Address {
+    city: String,
+}
+
+#[derive(Deserialize, AsimovOutput)]
+struct Person {
+    name: String,
+    #[serde(flatten)]
+    address: Address,
+}
+
+fn main() {}
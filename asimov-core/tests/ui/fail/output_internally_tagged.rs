@@ -0,0 +1,11 @@
+use asimov::prelude::*;
+use serde::Deserialize;
+
+#[derive(Deserialize, AsimovOutput)]
+#[serde(tag = "type")]
+enum Shape {
+    Circle { radius: f64 },
+    Square { side: f64 },
+}
+
+fn main() {}
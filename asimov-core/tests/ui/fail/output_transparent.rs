@@ -0,0 +1,10 @@
+use asimov::prelude::*;
+use serde::Deserialize;
+
+#[derive(Deserialize, AsimovOutput)]
+#[serde(transparent)]
+struct Score {
+    value: f64,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use asimov::prelude::*;
+
+#[derive(AsimovOutput)]
+union Number {
+    int: i32,
+    float: f32,
+}
+
+fn main() {}
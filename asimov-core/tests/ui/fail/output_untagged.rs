@@ -0,0 +1,11 @@
+use asimov::prelude::*;
+use serde::Deserialize;
+
+#[derive(Deserialize, AsimovOutput)]
+#[serde(untagged)]
+enum Answer {
+    Number(i64),
+    Text(String),
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use asimov::prelude::*;
+
+#[asimov(style = "comma", template = "{{ body }}")]
+struct Document {
+    body: String,
+}
+
+fn main() {}
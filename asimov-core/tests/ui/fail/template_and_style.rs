@@ -0,0 +1,8 @@
+use asimov::prelude::*;
+
+#[asimov(style = "lines", template = "{{ body }}")]
+struct Document {
+    body: String,
+}
+
+fn main() {}
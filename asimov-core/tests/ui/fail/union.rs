@@ -0,0 +1,9 @@
+use asimov::prelude::*;
+
+#[asimov]
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use asimov::prelude::*;
+
+#[asimov]
+struct Document {
+    #[asimov(rename = "text")]
+    body: String,
+}
+
+fn main() {}
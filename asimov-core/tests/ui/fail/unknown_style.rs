@@ -0,0 +1,8 @@
+use asimov::prelude::*;
+
+#[asimov(style = "yaml")]
+struct Document {
+    body: String,
+}
+
+fn main() {}
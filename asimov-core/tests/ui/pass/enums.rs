@@ -0,0 +1,42 @@
+use asimov::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[asimov]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum Shape {
+    Point,
+    Circle(f64),
+    Rectangle {
+        #[asimov(label = "w")]
+        width: f64,
+        height: f64,
+        #[asimov(skip)]
+        color: String,
+    },
+}
+
+#[asimov(style = "json")]
+#[derive(Serialize, Deserialize, Clone)]
+enum Event {
+    Started,
+    Stopped { code: i32 },
+}
+
+fn main() {
+    assert_eq!(Shape::Point.render().unwrap(), "Point");
+    assert_eq!(Shape::Circle(1.5).render().unwrap(), "Circle(1.5)");
+
+    let rectangle = Shape::Rectangle {
+        width: 2.0,
+        height: 3.0,
+        color: "red".to_string(),
+    };
+    assert_eq!(rectangle.render().unwrap(), "Rectangle(w: 2, 3)");
+    assert_eq!(rectangle.key(), rectangle);
+
+    assert_eq!(Event::Started.render().unwrap(), "\"Started\"");
+
+    let stopped: serde_json::Value =
+        serde_json::from_str(&Event::Stopped { code: 1 }.render().unwrap()).unwrap();
+    assert_eq!(stopped, serde_json::json!({"Stopped": {"code": 1}}));
+}
@@ -0,0 +1,19 @@
+use asimov::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[asimov]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Note {
+    title: String,
+    body: String,
+}
+
+fn main() {
+    let note = Note {
+        title: "Groceries".to_string(),
+        body: "Milk".to_string(),
+    };
+
+    assert_eq!(note.render().unwrap(), "Groceries, Milk");
+    assert_eq!(note.key(), note);
+}
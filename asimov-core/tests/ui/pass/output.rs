@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use asimov::prelude::*;
+use serde::Deserialize;
+use serde_json::json;
+
+/// A customer review.
+#[derive(Deserialize, AsimovOutput)]
+#[serde(rename_all = "camelCase")]
+struct Review {
+    /// Name of the reviewer.
+    author_name: String,
+    /// Score between 1 and 5.
+    score: u8,
+    #[serde(rename = "tags")]
+    keywords: Vec<String>,
+    summary: Option<String>,
+    #[serde(default)]
+    votes: HashMap<String, u32>,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    internal: bool,
+}
+
+#[derive(Deserialize, AsimovOutput)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum Mood {
+    VeryHappy,
+    Sad,
+}
+
+#[derive(Deserialize, AsimovOutput)]
+enum Action {
+    /// Do nothing.
+    Wait,
+    Move {
+        x: i32,
+        y: i32,
+    },
+    Say(String),
+}
+
+#[derive(Deserialize, AsimovOutput)]
+struct Point(f32, f32);
+
+fn main() {
+    assert_eq!(
+        Review::json_schema(),
+        json!({
+            "type": "object",
+            "description": "A customer review.",
+            "properties": {
+                "authorName": { "type": "string", "description": "Name of the reviewer." },
+                "score": { "type": "integer", "description": "Score between 1 and 5." },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "summary": { "type": "string" },
+                "votes": { "type": "object", "additionalProperties": { "type": "integer" } },
+            },
+            "required": ["authorName", "score", "tags"],
+            "additionalProperties": false,
+        })
+    );
+
+    assert_eq!(
+        Mood::json_schema(),
+        json!({ "type": "string", "enum": ["VERY_HAPPY", "SAD"] })
+    );
+    assert!(
+        Mood::format_instructions().starts_with("Respond only with one of \"VERY_HAPPY\", \"SAD\"")
+    );
+
+    let action = Action::json_schema();
+    assert_eq!(
+        action["oneOf"][0],
+        json!({ "const": "Wait", "description": "Do nothing." })
+    );
+    assert_eq!(action["oneOf"][1]["required"], json!(["Move"]));
+    assert_eq!(
+        action["oneOf"][2]["properties"]["Say"],
+        json!({ "type": "string" })
+    );
+
+    assert_eq!(
+        Point::json_schema()["prefixItems"]
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let instructions = Review::format_instructions();
+    assert!(instructions.contains("- \"authorName\" (string): Name of the reviewer."));
+    assert!(instructions.contains("- \"summary\" (string, optional)"));
+}
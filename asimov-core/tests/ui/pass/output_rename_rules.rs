@@ -0,0 +1,85 @@
+use asimov::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Serialize, Deserialize, AsimovOutput)]
+#[serde(rename_all = "lowercase")]
+struct Lower {
+    author_name: String,
+}
+
+#[derive(Serialize, Deserialize, AsimovOutput)]
+#[serde(rename_all = "UPPERCASE")]
+struct Upper {
+    author_name: String,
+}
+
+#[derive(Serialize, Deserialize, AsimovOutput)]
+#[serde(rename_all = "PascalCase")]
+struct Pascal {
+    author_name: String,
+}
+
+#[derive(Serialize, Deserialize, AsimovOutput)]
+#[serde(rename_all = "kebab-case")]
+enum Kind {
+    VeryHappy,
+    Sad,
+}
+
+#[derive(Serialize, Deserialize, AsimovOutput)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    VeryHigh,
+    Low,
+}
+
+/// Names of the properties in the schema of `T`, and in the JSON serde produces for `value`.
+fn names<T: AsimovOutput + Serialize>(value: &T) -> (Vec<String>, Vec<String>) {
+    let schema: Vec<String> = T::json_schema()["properties"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    let serialized: Vec<String> = serde_json::to_value(value)
+        .unwrap()
+        .as_object()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    (schema, serialized)
+}
+
+fn main() {
+    let author_name = String::from("Ada");
+
+    let (schema, serialized) = names(&Lower {
+        author_name: author_name.clone(),
+    });
+    assert_eq!(schema, ["author_name"]);
+    assert_eq!(schema, serialized);
+
+    let (schema, serialized) = names(&Upper {
+        author_name: author_name.clone(),
+    });
+    assert_eq!(schema, ["AUTHOR_NAME"]);
+    assert_eq!(schema, serialized);
+
+    let (schema, serialized) = names(&Pascal { author_name });
+    assert_eq!(schema, ["AuthorName"]);
+    assert_eq!(schema, serialized);
+
+    assert_eq!(
+        Kind::json_schema(),
+        json!({ "type": "string", "enum": ["very-happy", "sad"] })
+    );
+    assert_eq!(serde_json::to_value(Kind::VeryHappy).unwrap(), "very-happy");
+
+    assert_eq!(
+        Level::json_schema(),
+        json!({ "type": "string", "enum": ["veryhigh", "low"] })
+    );
+    assert_eq!(serde_json::to_value(Level::VeryHigh).unwrap(), "veryhigh");
+}
@@ -0,0 +1,27 @@
+use asimov::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[asimov(key = 0)]
+#[derive(Serialize, Deserialize, Clone)]
+struct Score(String, #[asimov(label = "points")] i32);
+
+#[asimov(style = "lines")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Wrapper(String);
+
+#[asimov(template = "{{ _0 }} scored {{ _1 }}")]
+#[derive(Serialize, Deserialize, Clone)]
+struct Sentence(String, i32);
+
+fn main() {
+    let score = Score("Ada".to_string(), 3);
+    assert_eq!(score.render().unwrap(), "Ada, points: 3");
+    assert_eq!(score.key(), "Ada");
+
+    let wrapper = Wrapper("text".to_string());
+    assert_eq!(wrapper.render().unwrap(), "0: text");
+    assert_eq!(wrapper.key(), wrapper);
+
+    let sentence = Sentence("Ada".to_string(), 3);
+    assert_eq!(sentence.render().unwrap(), "Ada scored 3");
+}
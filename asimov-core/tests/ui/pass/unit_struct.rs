@@ -0,0 +1,15 @@
+use asimov::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[asimov]
+#[derive(Serialize, Deserialize, Clone)]
+struct Marker;
+
+#[asimov(style = "json")]
+#[derive(Serialize, Deserialize, Clone)]
+struct JsonMarker;
+
+fn main() {
+    assert_eq!(Marker.render().unwrap(), "Marker");
+    assert_eq!(JsonMarker.render().unwrap(), "\"JsonMarker\"");
+}
@@ -1,93 +1,511 @@
 extern crate proc_macro;
 use darling::Error;
-use darling::{ast::NestedMeta, FromMeta};
+use darling::{ast::NestedMeta, FromField, FromMeta};
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, ItemStruct};
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Index, Member};
+
+mod output;
 
 #[derive(Debug, FromMeta)]
 struct AsimovMacroAttributes {
     #[darling(default)]
-    key: Option<syn::Ident>,
+    key: Option<KeyField>,
+    /// How fields are laid out when rendering the struct, comma-separated
+    /// when not given.
+    #[darling(default)]
+    style: Option<RenderStyle>,
+    /// Tera template rendering the struct, with one variable per field.
+    #[darling(default)]
+    template: Option<String>,
+}
+
+/// Field used as the `Embeddable` key: a field name, or an index for tuple structs.
+#[derive(Debug)]
+struct KeyField(Member);
+
+impl FromMeta for KeyField {
+    fn from_expr(expr: &syn::Expr) -> darling::Result<Self> {
+        match expr {
+            syn::Expr::Path(path) => path
+                .path
+                .get_ident()
+                .map(|ident| KeyField(Member::Named(ident.clone())))
+                .ok_or_else(|| Error::custom("expected a field name").with_span(expr)),
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(index),
+                ..
+            }) => Ok(KeyField(Member::Unnamed(Index {
+                index: index.base10_parse()?,
+                span: index.span(),
+            }))),
+            _ => Err(Error::custom("expected a field name or a field index").with_span(expr)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, FromMeta)]
+enum RenderStyle {
+    /// `value, value, ...`
+    #[default]
+    #[darling(rename = "comma")]
+    Comma,
+    /// One `label: value` line per field.
+    #[darling(rename = "lines")]
+    Lines,
+    /// A JSON object with one entry per field.
+    #[darling(rename = "json")]
+    Json,
+}
+
+/// Field-level `#[asimov(...)]` attributes.
+#[derive(Debug, FromField)]
+#[darling(attributes(asimov))]
+struct AsimovFieldAttributes {
+    /// Leave the field out of the rendered output.
+    #[darling(default)]
+    skip: bool,
+    /// Label used instead of the field name.
+    #[darling(default)]
+    label: Option<String>,
+    /// Render the field with `serde_json` instead of `Input::render`.
+    #[darling(default)]
+    json: bool,
+}
+
+/// A field of a struct or of an enum variant.
+struct FieldInfo {
+    member: Member,
+    ty: syn::Type,
+    /// Variable bound to the field when destructuring an enum variant.
+    binding: syn::Ident,
+    /// Variable name of the field in templates.
+    var: String,
+    label: String,
+    labeled: bool,
+    skip: bool,
+    json: bool,
+}
+
+impl FieldInfo {
+    fn collect(fields: &Fields) -> darling::Result<Vec<Self>> {
+        let mut errors = Error::accumulator();
+
+        let infos = fields
+            .iter()
+            .enumerate()
+            .filter_map(|(index, field)| {
+                let attrs = errors.handle(AsimovFieldAttributes::from_field(field))?;
+                let (member, binding, var) = match &field.ident {
+                    Some(ident) => (
+                        Member::Named(ident.clone()),
+                        ident.clone(),
+                        ident.to_string(),
+                    ),
+                    None => (
+                        Member::Unnamed(Index::from(index)),
+                        format_ident!("__field{}", index),
+                        format!("_{index}"),
+                    ),
+                };
+                let name = match &member {
+                    Member::Named(ident) => ident.to_string(),
+                    Member::Unnamed(index) => index.index.to_string(),
+                };
+
+                Some(FieldInfo {
+                    member,
+                    ty: field.ty.clone(),
+                    binding,
+                    var,
+                    labeled: attrs.label.is_some(),
+                    label: attrs.label.unwrap_or(name),
+                    skip: attrs.skip,
+                    json: attrs.json,
+                })
+            })
+            .collect();
+
+        errors.finish_with(infos)
+    }
 }
 
+/// Path to the `asimov` crate, as seen from the crate using the macro.
+pub(crate) fn asimov_crate() -> TokenStream2 {
+    match proc_macro_crate::crate_name("asimov") {
+        Ok(proc_macro_crate::FoundCrate::Name(name)) => {
+            let name = syn::Ident::new(&name, Span::call_site());
+            quote!(::#name)
+        }
+        Ok(proc_macro_crate::FoundCrate::Itself) | Err(_) => quote!(::asimov),
+    }
+}
+
+/// Field-level attributes are only meant for this macro: strip them from the emitted item.
+fn strip_field_attributes(input: &mut DeriveInput) {
+    let strip = |fields: &mut Fields| {
+        for field in fields.iter_mut() {
+            field.attrs.retain(|a| !a.path().is_ident("asimov"));
+        }
+    };
+
+    match &mut input.data {
+        Data::Struct(data) => strip(&mut data.fields),
+        Data::Enum(data) => data.variants.iter_mut().for_each(|v| strip(&mut v.fields)),
+        Data::Union(_) => {}
+    }
+}
+
+/// Implements `Input` and `Embeddable` for a struct or an enum.
+///
+/// Struct-level options:
+/// - `key = field`: field used as the `Embeddable` key (`key = 0` for tuple
+///   structs). Without a key, a clone of the value itself is the key, which
+///   requires the type to be `Clone`, `Serialize` and `Deserialize`.
+/// - `style = "comma" | "lines" | "json"`: renders fields as `a, b`, as
+///   `label: value` lines, or as a JSON object. Defaults to `"comma"`.
+/// - `template = "..."`: renders a Tera template where each field is a
+///   variable (`_0`, `_1`, ... for tuple structs). Not supported on enums.
+///
+/// Field-level options: `#[asimov(skip)]`, `#[asimov(label = "...")]` and
+/// `#[asimov(json)]` to render the field with `serde_json`.
+///
+/// Unit structs and unit variants render as their name. Variants with fields
+/// render as `Variant(a, b)`, `Variant` followed by `label: value` lines, or
+/// `{"Variant": {...}}` depending on the style.
 #[proc_macro_attribute]
 pub fn asimov(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as ItemStruct);
-    let struct_name = &input.ident;
+    let mut input = parse_macro_input!(item as DeriveInput);
+
+    let expanded = NestedMeta::parse_meta_list(attr.into())
+        .map_err(Error::from)
+        .and_then(|attr_list| AsimovMacroAttributes::from_list(&attr_list))
+        .and_then(|asimov_attr| expand(&asimov_attr, &input));
+
+    strip_field_attributes(&mut input);
 
-    let attr_list = match NestedMeta::parse_meta_list(attr.into()) {
-        Ok(v) => v,
+    match expanded {
+        Ok(impls) => quote! {
+            #input
+
+            #impls
+        },
         Err(e) => {
-            return TokenStream::from(Error::from(e).write_errors());
+            let errors = e.write_errors();
+            quote! {
+                #input
+
+                #errors
+            }
+        }
+    }
+    .into()
+}
+
+/// Implements `AsimovOutput` for a struct or an enum, describing the JSON
+/// expected from a LLM.
+///
+/// Doc comments on the type, its fields and its variants become descriptions
+/// in the generated JSON schema. `#[serde(rename = "...")]`,
+/// `#[serde(rename_all = "...")]`, `#[serde(default)]` and `#[serde(skip)]`
+/// are taken into account. Enums with only unit variants map to a string
+/// enum, other enums to serde's externally tagged representation.
+/// `tag`, `content`, `untagged`, `flatten` and `transparent` are rejected.
+#[proc_macro_derive(AsimovOutput)]
+pub fn derive_asimov_output(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+
+    output::expand(&input)
+        .unwrap_or_else(|e| e.write_errors())
+        .into()
+}
+
+fn expand(
+    asimov_attr: &AsimovMacroAttributes,
+    input: &DeriveInput,
+) -> darling::Result<TokenStream2> {
+    let krate = asimov_crate();
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let style = asimov_attr.style.unwrap_or_default();
+
+    if asimov_attr.template.is_some() && asimov_attr.style.is_some() {
+        return Err(Error::custom(
+            "`template` and `style` cannot be used together",
+        ));
+    }
+
+    let (render_body, key_impl) = match &input.data {
+        Data::Struct(data) => {
+            let fields = FieldInfo::collect(&data.fields)?;
+            let render_body =
+                if matches!(data.fields, Fields::Unit) && asimov_attr.template.is_none() {
+                    render_name(&krate, &name.to_string(), style)
+                } else {
+                    render_fields(&krate, asimov_attr, &fields, |f| {
+                        let member = &f.member;
+                        quote!(&self.#member)
+                    })
+                };
+            (render_body, key_impl(&krate, input, asimov_attr, &fields)?)
+        }
+        Data::Enum(data) => {
+            if asimov_attr.template.is_some() {
+                return Err(Error::custom("`template` is not supported on enums"));
+            }
+            if let Some(KeyField(member)) = &asimov_attr.key {
+                return Err(Error::custom("`key` is not supported on enums").with_span(member));
+            }
+
+            let mut errors = Error::accumulator();
+            let arms: Vec<_> = data
+                .variants
+                .iter()
+                .filter_map(|variant| {
+                    let fields = errors.handle(FieldInfo::collect(&variant.fields))?;
+                    Some(render_variant(&krate, style, variant, &fields))
+                })
+                .collect();
+            errors.finish()?;
+
+            let render_body = quote! {
+                match self {
+                    #(#arms)*
+                }
+            };
+            (render_body, key_impl(&krate, input, asimov_attr, &[])?)
+        }
+        Data::Union(data) => {
+            return Err(
+                Error::custom("`asimov` can only be used on structs and enums")
+                    .with_span(&data.union_token),
+            );
         }
     };
 
-    let asimov_attr = match AsimovMacroAttributes::from_list(&attr_list) {
-        Ok(v) => v,
-        Err(e) => {
-            return TokenStream::from(e.write_errors());
+    Ok(quote! {
+        impl #impl_generics #krate::Input for #name #ty_generics #where_clause {
+            fn render(&self) -> ::std::result::Result<::std::string::String, #krate::AsimovError> {
+                #render_body
+            }
+        }
+
+        #key_impl
+    })
+}
+
+/// Renders a unit struct or a unit variant.
+fn render_name(krate: &TokenStream2, name: &str, style: RenderStyle) -> TokenStream2 {
+    match style {
+        RenderStyle::Json => quote! {
+            #krate::serde_json::to_string(#name)
+                .map_err(|e| #krate::AsimovError::Input(e.to_string()))
+        },
+        RenderStyle::Comma | RenderStyle::Lines => {
+            quote! { ::std::result::Result::Ok(::std::string::String::from(#name)) }
+        }
+    }
+}
+
+/// Match arm rendering one variant of an enum.
+fn render_variant(
+    krate: &TokenStream2,
+    style: RenderStyle,
+    variant: &syn::Variant,
+    fields: &[FieldInfo],
+) -> TokenStream2 {
+    let ident = &variant.ident;
+    let name = ident.to_string();
+
+    let bindings = fields.iter().map(|f| {
+        let binding = if f.skip {
+            format_ident!("_")
+        } else {
+            f.binding.clone()
+        };
+        match &f.member {
+            // Named fields are bound to their own name: use the shorthand pattern.
+            Member::Named(member) if !f.skip => member.to_token_stream(),
+            Member::Named(member) => quote!(#member: #binding),
+            Member::Unnamed(_) => binding.into_token_stream(),
+        }
+    });
+
+    let pattern = match &variant.fields {
+        Fields::Named(_) => quote!(Self::#ident { #(#bindings),* }),
+        Fields::Unnamed(_) => quote!(Self::#ident(#(#bindings),*)),
+        Fields::Unit => {
+            let render = render_name(krate, &name, style);
+            return quote! { Self::#ident => #render, };
         }
     };
 
-    let render_impl = input.fields.iter().map(|f| {
-        let name = &f.ident.clone().expect("Field without a name");
-        quote! {
+    let attrs = AsimovMacroAttributes {
+        key: None,
+        style: Some(style),
+        template: None,
+    };
+    let render = render_fields(krate, &attrs, fields, |f| f.binding.to_token_stream());
+
+    let wrap = match style {
+        RenderStyle::Comma => quote! { ::std::format!("{}({})", #name, rendered) },
+        RenderStyle::Lines => quote! { ::std::format!("{}\n{}", #name, rendered) },
+        RenderStyle::Json => quote! {
             {
-                // Directly call `render` trusting that the correct implementation will be used
-                // This could be a custom implementation or the default one for types that implement `Display`
-                let result: Result<String, AsimovError> = self.#name.render();
-                result
+                let fields: #krate::serde_json::Value = #krate::serde_json::from_str(&rendered)
+                    .map_err(|e| #krate::AsimovError::Input(e.to_string()))?;
+                let mut object = #krate::serde_json::Map::new();
+                object.insert(#name.to_string(), fields);
+                #krate::serde_json::to_string_pretty(&object)
+                    .map_err(|e| #krate::AsimovError::Input(e.to_string()))?
             }
+        },
+    };
+
+    quote! {
+        #pattern => {
+            let rendered = #render?;
+            ::std::result::Result::Ok(#wrap)
         }
-    });
+    }
+}
 
-    let embeddable_impl = if let Some(key) = asimov_attr.key.clone() {
-        let key_type = input
-            .fields
-            .iter()
-            .find_map(|f| {
-                if f.ident.as_ref() == Some(&key) {
-                    Some(f.ty.clone())
-                } else {
-                    None
+/// Expression rendering a list of fields, evaluating to a `Result<String, AsimovError>`.
+///
+/// `access` produces a reference to the value of a field.
+fn render_fields(
+    krate: &TokenStream2,
+    asimov_attr: &AsimovMacroAttributes,
+    fields: &[FieldInfo],
+    access: impl Fn(&FieldInfo) -> TokenStream2,
+) -> TokenStream2 {
+    let rendered: Vec<_> = fields
+        .iter()
+        .filter(|f| !f.skip)
+        .map(|f| {
+            let field = access(f);
+            let value = if f.json {
+                quote! {
+                    #krate::serde_json::to_string(#field)
+                        .map_err(|e| #krate::AsimovError::Input(e.to_string()))
                 }
-            })
-            .expect("Key field not found in struct");
+            } else {
+                // Directly call `render` trusting that the correct implementation will be used
+                // This could be a custom implementation or the default one for types that implement `Display`
+                quote! { #krate::Input::render(#field) }
+            };
+            (f, value)
+        })
+        .collect();
 
-        quote! {
-            impl Embeddable for #struct_name {
-                type Key = #key_type;
+    if let Some(template) = &asimov_attr.template {
+        let inserts = rendered.iter().map(|(f, value)| {
+            let var = &f.var;
+            quote! { context.insert(#var, &#value?); }
+        });
+        return quote! {
+            {
+                let mut context = #krate::tera::Context::new();
+                #(#inserts)*
+                #krate::tera::Tera::one_off(#template, &context, false)
+                    .map_err(|e| #krate::AsimovError::Input(e.to_string()))
+            }
+        };
+    }
 
-                fn key(&self) -> Self::Key {
-                    self.#key.clone()
+    match asimov_attr.style.unwrap_or_default() {
+        RenderStyle::Comma => {
+            let parts = rendered.iter().map(|(f, value)| {
+                if f.labeled {
+                    let label = &f.label;
+                    quote! { ::std::format!("{}: {}", #label, #value?) }
+                } else {
+                    quote! { #value? }
+                }
+            });
+            quote! {
+                {
+                    let fields_rendered: ::std::vec::Vec<::std::string::String> = ::std::vec![#(#parts),*];
+                    ::std::result::Result::<_, #krate::AsimovError>::Ok(fields_rendered.join(", "))
                 }
             }
         }
-    } else {
-        quote! {
-            impl Embeddable for #struct_name {
-                type Key = Self;
-
-                fn key(&self) -> &Self::Key {
-                    &self
+        RenderStyle::Lines => {
+            let parts = rendered.iter().map(|(f, value)| {
+                let label = &f.label;
+                quote! { ::std::format!("{}: {}", #label, #value?) }
+            });
+            quote! {
+                {
+                    let fields_rendered: ::std::vec::Vec<::std::string::String> = ::std::vec![#(#parts),*];
+                    ::std::result::Result::<_, #krate::AsimovError>::Ok(fields_rendered.join("\n"))
                 }
             }
         }
-    };
-
-    let tok_stream = quote! {
-        #input
-
-        impl Input for #struct_name {
-            fn render(&self) -> Result<String, AsimovError> {
-                let fields_rendered: Result<Vec<String>, AsimovError> = vec![#(#render_impl),*].into_iter().collect();
-                fields_rendered.map(|v| v.join(", "))
+        RenderStyle::Json => {
+            let inserts = rendered.iter().map(|(f, _)| {
+                let label = &f.label;
+                let field = access(f);
+                quote! {
+                    object.insert(
+                        #label.to_string(),
+                        #krate::serde_json::to_value(#field)
+                            .map_err(|e| #krate::AsimovError::Input(e.to_string()))?,
+                    );
+                }
+            });
+            quote! {
+                {
+                    let mut object = #krate::serde_json::Map::new();
+                    #(#inserts)*
+                    #krate::serde_json::to_string_pretty(&object)
+                        .map_err(|e| #krate::AsimovError::Input(e.to_string()))
+                }
             }
         }
+    }
+}
 
-        #embeddable_impl
+/// `Embeddable` implementation, keyed on the `key` field if any, or on the value itself.
+fn key_impl(
+    krate: &TokenStream2,
+    input: &DeriveInput,
+    asimov_attr: &AsimovMacroAttributes,
+    fields: &[FieldInfo],
+) -> darling::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let (key_type, key) = match &asimov_attr.key {
+        Some(KeyField(member)) => {
+            let field = fields.iter().find(|f| &f.member == member).ok_or_else(|| {
+                Error::custom(format!(
+                    "key field `{}` not found in `{name}`",
+                    member.to_token_stream()
+                ))
+                .with_span(member)
+            })?;
+            let ty = &field.ty;
+            (
+                ty.to_token_stream(),
+                quote! { ::std::clone::Clone::clone(&self.#member) },
+            )
+        }
+        None => (
+            // Point errors about missing `Clone`/`Serialize`/`Deserialize` impls at the type.
+            quote_spanned! { name.span()=> Self },
+            quote! { ::std::clone::Clone::clone(self) },
+        ),
     };
 
-    tok_stream.into()
+    Ok(quote! {
+        impl #impl_generics #krate::Embeddable for #name #ty_generics #where_clause {
+            type Key = #key_type;
+
+            fn key(&self) -> Self::Key {
+                #key
+            }
+        }
+    })
 }
@@ -0,0 +1,439 @@
+//! `#[derive(AsimovOutput)]`: JSON schema and format instructions for output types.
+use darling::Error;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Attribute, Data, DeriveInput, Fields, LitStr};
+
+use crate::asimov_crate;
+
+/// Doc comment of an item, with the leading space of each line removed.
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(doc),
+                        ..
+                    }),
+                ..
+            }) => Some(doc.value()),
+            _ => None,
+        })
+        .map(|line| {
+            line.strip_prefix(' ')
+                .unwrap_or(&line)
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+
+    let doc = lines.join("\n").trim().to_string();
+    (!doc.is_empty()).then_some(doc)
+}
+
+/// `#[serde(...)]` attributes changing the representation of a type, which
+/// the generated schema does not describe.
+const UNSUPPORTED: [&str; 5] = ["tag", "content", "untagged", "flatten", "transparent"];
+
+/// The subset of `#[serde(...)]` attributes that changes the expected JSON.
+#[derive(Default)]
+struct SerdeAttributes {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    default: bool,
+    skip: bool,
+}
+
+impl SerdeAttributes {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut serde = SerdeAttributes::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                let path = &meta.path;
+                if path.is_ident("rename") || path.is_ident("rename_all") {
+                    let value = if meta.input.peek(syn::Token![=]) {
+                        Some(meta.value()?.parse::<LitStr>()?.value())
+                    } else {
+                        // `rename(serialize = "...", deserialize = "...")`: the LLM produces
+                        // what we deserialize.
+                        let mut deserialize = None;
+                        meta.parse_nested_meta(|nested| {
+                            let value = nested.value()?.parse::<LitStr>()?.value();
+                            if nested.path.is_ident("deserialize") {
+                                deserialize = Some(value);
+                            }
+                            Ok(())
+                        })?;
+                        deserialize
+                    };
+                    if path.is_ident("rename") {
+                        serde.rename = value.or(serde.rename.take());
+                    } else {
+                        serde.rename_all = value.or(serde.rename_all.take());
+                    }
+                } else if path.is_ident("default") {
+                    serde.default = true;
+                    if meta.input.peek(syn::Token![=]) {
+                        meta.value()?.parse::<LitStr>()?;
+                    }
+                } else if path.is_ident("skip") || path.is_ident("skip_deserializing") {
+                    serde.skip = true;
+                } else if let Some(unsupported) = UNSUPPORTED
+                    .iter()
+                    .find(|attribute| path.is_ident(attribute))
+                {
+                    return Err(meta.error(format!(
+                        "unsupported serde attribute `{unsupported}`: `AsimovOutput` only \
+                         describes serde's default representation"
+                    )));
+                } else if meta.input.peek(syn::Token![=]) {
+                    meta.value()?.parse::<syn::Expr>()?;
+                } else if meta.input.peek(syn::token::Paren) {
+                    meta.parse_nested_meta(|nested| {
+                        if nested.input.peek(syn::Token![=]) {
+                            nested.value()?.parse::<syn::Expr>()?;
+                        }
+                        Ok(())
+                    })?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(serde)
+    }
+}
+
+/// A serde `rename_all` rule, applied as serde does.
+#[derive(Debug, Clone, Copy)]
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(rule: &str) -> darling::Result<Self> {
+        Ok(match rule {
+            "lowercase" => Self::Lower,
+            "UPPERCASE" => Self::Upper,
+            "PascalCase" => Self::Pascal,
+            "camelCase" => Self::Camel,
+            "snake_case" => Self::Snake,
+            "SCREAMING_SNAKE_CASE" => Self::ScreamingSnake,
+            "kebab-case" => Self::Kebab,
+            "SCREAMING-KEBAB-CASE" => Self::ScreamingKebab,
+            _ => return Err(Error::custom(format!("unknown rename rule `{rule}`"))),
+        })
+    }
+
+    /// Applies the rule to a variant name, assumed to be `PascalCase`.
+    fn apply_to_variant(self, variant: &str) -> String {
+        match self {
+            Self::Pascal => variant.to_owned(),
+            Self::Lower => variant.to_ascii_lowercase(),
+            Self::Upper => variant.to_ascii_uppercase(),
+            Self::Camel => lowercase_first(variant),
+            Self::Snake => {
+                let mut snake = String::new();
+                for (i, ch) in variant.char_indices() {
+                    if i > 0 && ch.is_uppercase() {
+                        snake.push('_');
+                    }
+                    snake.push(ch.to_ascii_lowercase());
+                }
+                snake
+            }
+            Self::ScreamingSnake => Self::Snake.apply_to_variant(variant).to_ascii_uppercase(),
+            Self::Kebab => Self::Snake.apply_to_variant(variant).replace('_', "-"),
+            Self::ScreamingKebab => Self::ScreamingSnake
+                .apply_to_variant(variant)
+                .replace('_', "-"),
+        }
+    }
+
+    /// Applies the rule to a field name, assumed to be `snake_case`.
+    fn apply_to_field(self, field: &str) -> String {
+        match self {
+            Self::Lower | Self::Snake => field.to_owned(),
+            Self::Upper | Self::ScreamingSnake => field.to_ascii_uppercase(),
+            Self::Pascal => {
+                let mut pascal = String::new();
+                let mut capitalize = true;
+                for ch in field.chars() {
+                    if ch == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(ch.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(ch);
+                    }
+                }
+                pascal
+            }
+            Self::Camel => lowercase_first(&Self::Pascal.apply_to_field(field)),
+            Self::Kebab => field.replace('_', "-"),
+            Self::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+fn lowercase_first(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
+
+/// Name of a field or variant in the JSON output.
+///
+/// `apply` is [`RenameRule::apply_to_field`] or [`RenameRule::apply_to_variant`].
+fn json_name(
+    ident: &syn::Ident,
+    serde: &SerdeAttributes,
+    rename_all: Option<&str>,
+    apply: fn(RenameRule, &str) -> String,
+) -> darling::Result<String> {
+    if let Some(rename) = &serde.rename {
+        return Ok(rename.clone());
+    }
+    let name = ident.to_string();
+    let name = name.strip_prefix("r#").unwrap_or(&name);
+    match rename_all {
+        Some(rule) => RenameRule::parse(rule)
+            .map(|rule| apply(rule, name))
+            .map_err(|e| e.with_span(ident)),
+        None => Ok(name.to_string()),
+    }
+}
+
+/// Expression adding a `description` to the schema held in the variable `schema`.
+fn describe(krate: &TokenStream2, doc: Option<String>) -> TokenStream2 {
+    match doc {
+        Some(doc) => quote! {
+            if let ::std::option::Option::Some(object) = schema.as_object_mut() {
+                object.insert(
+                    ::std::string::String::from("description"),
+                    #krate::serde_json::Value::from(#doc),
+                );
+            }
+        },
+        None => quote! {},
+    }
+}
+
+/// Expression evaluating to the schema of a list of fields.
+///
+/// Named fields map to an object, a single unnamed field to the schema of its
+/// type and several unnamed fields to a fixed-length array.
+fn fields_schema(
+    krate: &TokenStream2,
+    fields: &Fields,
+    rename_all: Option<&str>,
+    container_default: bool,
+) -> darling::Result<TokenStream2> {
+    let mut errors = Error::accumulator();
+
+    let schema = match fields {
+        Fields::Named(named) => {
+            let properties: Vec<_> = named
+                .named
+                .iter()
+                .filter_map(|field| {
+                    let serde =
+                        errors.handle(SerdeAttributes::parse(&field.attrs).map_err(Error::from))?;
+                    if serde.skip {
+                        return None;
+                    }
+                    let ident = field.ident.as_ref()?;
+                    let name = errors.handle(json_name(
+                        ident,
+                        &serde,
+                        rename_all,
+                        RenameRule::apply_to_field,
+                    ))?;
+                    let ty = &field.ty;
+                    let description = describe(krate, doc_comment(&field.attrs));
+                    let required = if serde.default || container_default {
+                        quote! {}
+                    } else {
+                        quote! {
+                            if !<#ty as #krate::AsimovOutput>::optional() {
+                                required.push(#krate::serde_json::Value::from(#name));
+                            }
+                        }
+                    };
+                    Some(quote! {
+                        {
+                            let mut schema = <#ty as #krate::AsimovOutput>::json_schema();
+                            #description
+                            properties.insert(::std::string::String::from(#name), schema);
+                            #required
+                        }
+                    })
+                })
+                .collect();
+
+            quote! {
+                {
+                    let mut properties = #krate::serde_json::Map::new();
+                    let mut required = ::std::vec::Vec::<#krate::serde_json::Value>::new();
+                    #(#properties)*
+                    #krate::serde_json::json!({
+                        "type": "object",
+                        "properties": properties,
+                        "required": required,
+                        "additionalProperties": false,
+                    })
+                }
+            }
+        }
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => {
+            let ty = &unnamed.unnamed[0].ty;
+            quote! { <#ty as #krate::AsimovOutput>::json_schema() }
+        }
+        Fields::Unnamed(unnamed) => {
+            let items = unnamed.unnamed.iter().map(|field| {
+                let ty = &field.ty;
+                quote! { (<#ty as #krate::AsimovOutput>::json_schema()) }
+            });
+            let len = unnamed.unnamed.len();
+            quote! {
+                #krate::serde_json::json!({
+                    "type": "array",
+                    "prefixItems": [#(#items),*],
+                    "minItems": #len,
+                    "maxItems": #len,
+                })
+            }
+        }
+        Fields::Unit => quote! { #krate::serde_json::json!({ "type": "null" }) },
+    };
+
+    errors.finish_with(schema)
+}
+
+pub(crate) fn expand(input: &DeriveInput) -> darling::Result<TokenStream2> {
+    let krate = asimov_crate();
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let serde = SerdeAttributes::parse(&input.attrs)?;
+    let rename_all = serde.rename_all.as_deref();
+
+    let schema = match &input.data {
+        Data::Struct(data) => fields_schema(&krate, &data.fields, rename_all, serde.default)?,
+        Data::Enum(data)
+            if data
+                .variants
+                .iter()
+                .all(|v| matches!(v.fields, Fields::Unit)) =>
+        {
+            let mut errors = Error::accumulator();
+            let values: Vec<_> = data
+                .variants
+                .iter()
+                .filter_map(|variant| {
+                    let serde = errors
+                        .handle(SerdeAttributes::parse(&variant.attrs).map_err(Error::from))?;
+                    if serde.skip {
+                        return None;
+                    }
+                    errors.handle(json_name(
+                        &variant.ident,
+                        &serde,
+                        rename_all,
+                        RenameRule::apply_to_variant,
+                    ))
+                })
+                .collect();
+            errors.finish()?;
+
+            quote! {
+                #krate::serde_json::json!({ "type": "string", "enum": [#(#values),*] })
+            }
+        }
+        Data::Enum(data) => {
+            // Serde's default, externally tagged representation.
+            let mut errors = Error::accumulator();
+            let variants: Vec<_> = data
+                .variants
+                .iter()
+                .filter_map(|variant| {
+                    let serde = errors
+                        .handle(SerdeAttributes::parse(&variant.attrs).map_err(Error::from))?;
+                    if serde.skip {
+                        return None;
+                    }
+                    let name = errors.handle(json_name(
+                        &variant.ident,
+                        &serde,
+                        rename_all,
+                        RenameRule::apply_to_variant,
+                    ))?;
+                    let description = describe(&krate, doc_comment(&variant.attrs));
+                    if matches!(variant.fields, Fields::Unit) {
+                        return Some(quote! {
+                            {
+                                let mut schema = #krate::serde_json::json!({ "const": #name });
+                                #description
+                                schema
+                            }
+                        });
+                    }
+                    let fields = errors.handle(fields_schema(
+                        &krate,
+                        &variant.fields,
+                        serde.rename_all.as_deref(),
+                        false,
+                    ))?;
+                    Some(quote! {
+                        {
+                            let mut schema = #krate::serde_json::json!({
+                                "type": "object",
+                                "properties": { #name: (#fields) },
+                                "required": [#name],
+                                "additionalProperties": false,
+                            });
+                            #description
+                            schema
+                        }
+                    })
+                })
+                .collect();
+            errors.finish()?;
+
+            quote! {
+                #krate::serde_json::json!({ "oneOf": [#((#variants)),*] })
+            }
+        }
+        Data::Union(data) => {
+            return Err(
+                Error::custom("`AsimovOutput` can only be derived for structs and enums")
+                    .with_span(&data.union_token),
+            );
+        }
+    };
+
+    let description = describe(&krate, doc_comment(&input.attrs));
+
+    Ok(quote! {
+        impl #impl_generics #krate::AsimovOutput for #name #ty_generics #where_clause {
+            fn json_schema() -> #krate::serde_json::Value {
+                let mut schema = #schema;
+                #description
+                schema
+            }
+        }
+    })
+}